@@ -4,7 +4,11 @@ use bytes::Buf;
 use criterion::*;
 use input_buffer::InputBuffer;
 
-use tungstenite::buffer::ReadBuffer;
+use tungstenite::{
+    buffer::ReadBuffer,
+    protocol::frame::FrameSocket,
+    storage::{ring_buffer::RingBuffer, U8RingBuffer},
+};
 
 const CHUNK_SIZE: usize = 4096;
 
@@ -105,6 +109,42 @@ fn heap_read_buffer(mut stream: impl Read) {
     while buffer.read_from(&mut stream).unwrap() != 0 {}
 }
 
+/// Read straight into the ring buffer, leaving a partial 1500 byte frame behind after every
+/// read, so the free space wraps around.
+#[inline]
+fn ring_read_buffer(mut stream: impl Read) {
+    let mut buffer = U8RingBuffer::new();
+    while buffer.read_from(&mut stream).unwrap() != 0 {
+        let len = buffer.0.len();
+        buffer.0.consume(len - len % 1500);
+    }
+}
+
+/// Pass `data` through `ring` in frames of `FRAME_SIZE`, borrowing each payload mutably and
+/// consuming it afterwards as the frame codec does. The ring is kept partially filled, so some
+/// frames wrap around.
+#[inline]
+fn ring_buffer(ring: &mut RingBuffer<u8>, data: &[u8]) {
+    const FRAME_SIZE: usize = 1500;
+    ring.clear();
+    assert_eq!(ring.enqueue_slice(&data[..FRAME_SIZE / 2]), FRAME_SIZE / 2);
+    for frame in data.chunks(FRAME_SIZE) {
+        assert_eq!(ring.enqueue_slice(frame), frame.len());
+        black_box(ring.peek_contiguous_mut(frame.len()));
+        ring.consume(frame.len());
+    }
+}
+
+/// Read binary frames of 1500 bytes through a `FrameSocket`, whose read buffer is mirrored
+/// with the `mirrored-buffer` feature.
+#[inline]
+fn frame_socket(frames: &[u8]) {
+    let mut socket = FrameSocket::new(Cursor::new(frames));
+    while let Some(frame) = socket.read().unwrap() {
+        black_box(frame.payload());
+    }
+}
+
 fn benchmark(c: &mut Criterion) {
     const STREAM_SIZE: usize = 1024 * 1024 * 4;
     let data: Vec<u8> = (0..STREAM_SIZE).map(|_| rand::random()).collect();
@@ -119,6 +159,33 @@ fn benchmark(c: &mut Criterion) {
     group.bench_function("ReadBuffer (heap)", |b| {
         b.iter(|| heap_read_buffer(black_box(stream.clone())))
     });
+    group
+        .bench_function("U8RingBuffer", |b| b.iter(|| ring_read_buffer(black_box(stream.clone()))));
+    group.finish();
+
+    let data = stream.into_inner();
+    let mut group = c.benchmark_group("ring buffer");
+    group.throughput(Throughput::Bytes(STREAM_SIZE as u64));
+    group.bench_function("peek and consume", |b| {
+        let mut ring = RingBuffer::new(vec![0; 65536]);
+        b.iter(|| ring_buffer(&mut ring, black_box(&data)))
+    });
+    #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+    group.bench_function("peek and consume (mirrored)", |b| {
+        let mut ring = RingBuffer::mirrored(65536).unwrap();
+        b.iter(|| ring_buffer(&mut ring, black_box(&data)))
+    });
+    group.finish();
+
+    let payloads = data.chunks_exact(1500);
+    let payload_size = STREAM_SIZE - payloads.remainder().len();
+    let frames: Vec<u8> = payloads
+        .flat_map(|payload| [0x82, 0x7e, 0x05, 0xdc].iter().chain(payload))
+        .copied()
+        .collect();
+    let mut group = c.benchmark_group("frame socket");
+    group.throughput(Throughput::Bytes(payload_size as u64));
+    group.bench_function("read", |b| b.iter(|| frame_socket(black_box(&frames))));
     group.finish();
 }
 
@@ -0,0 +1,55 @@
+//! Benchmarks for frame masking.
+use criterion::*;
+
+use tungstenite::protocol::frame::{
+    mask::{apply_mask_copy, apply_mask_fallback, apply_mask_fast32, apply_mask_simd},
+    FastMask, MaskGenerator, SecureMask,
+};
+
+const MASK: [u8; 4] = [0x6d, 0xb6, 0xb2, 0x80];
+
+fn benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate mask");
+    group.bench_function("secure", |b| b.iter(|| black_box(SecureMask.generate_mask())));
+    group.bench_function("fast", |b| b.iter(|| black_box(FastMask.generate_mask())));
+    group.finish();
+
+    for &size in &[16, 128, 1024, 16 * 1024, 1024 * 1024] {
+        // One extra byte allows to start at an unaligned offset.
+        let mut data: Vec<u8> = (0..=size).map(|_| rand::random()).collect();
+
+        for &offset in &[0, 1] {
+            let mut group = c.benchmark_group(format!("mask {} bytes, offset {}", size, offset));
+            group.throughput(Throughput::Bytes(size as u64));
+            let buf = &mut data[offset..offset + size];
+            group.bench_function("fallback", |b| {
+                b.iter(|| apply_mask_fallback(black_box(&mut *buf), MASK))
+            });
+            group.bench_function("fast32", |b| {
+                b.iter(|| apply_mask_fast32(black_box(&mut *buf), MASK))
+            });
+            group
+                .bench_function("simd", |b| b.iter(|| apply_mask_simd(black_box(&mut *buf), MASK)));
+            group.finish();
+        }
+
+        // Masking into an outgoing buffer, as done when formatting client frames.
+        let mut group = c.benchmark_group(format!("mask into buffer {} bytes", size));
+        group.throughput(Throughput::Bytes(size as u64));
+        let src = &data[..size];
+        let mut dst = vec![0; size];
+        group.bench_function("copy then simd", |b| {
+            b.iter(|| {
+                dst.copy_from_slice(black_box(src));
+                apply_mask_simd(&mut dst, MASK)
+            })
+        });
+        group.bench_function("fused copy", |b| {
+            b.iter(|| apply_mask_copy(&mut dst, black_box(src), MASK))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, benchmark);
+criterion_main!(benches);
@@ -28,7 +28,7 @@ fn main() {
             let mut websocket = accept_hdr(stream.unwrap(), callback).unwrap();
 
             loop {
-                let msg = websocket.read().unwrap();
+                let msg = websocket.read().unwrap().into_owned().unwrap();
                 if msg.is_binary() || msg.is_text() {
                     websocket.send(msg).unwrap();
                 }
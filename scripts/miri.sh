@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+# Runs the read buffer and frame parsing tests under Miri to check for undefined behaviour.
+set -euo pipefail
+set -x
+SOURCE_DIR=$(readlink -f "${BASH_SOURCE[0]}")
+SOURCE_DIR=$(dirname "$SOURCE_DIR")
+cd "${SOURCE_DIR}/.."
+
+rustup component add --toolchain nightly miri
+cargo +nightly miri test --lib storage::
+# The tests leak their buffer pools to borrow them for `'static`.
+MIRIFLAGS=-Zmiri-ignore-leaks cargo +nightly miri test --lib protocol::frame::tests
@@ -0,0 +1,18 @@
+#!/usr/bin/env bash
+# Builds the crate without `std`, with and without `alloc`, for a bare-metal target, building
+# `core` and `alloc` from source so the target does not need to be installed.
+set -euo pipefail
+set -x
+SOURCE_DIR=$(readlink -f "${BASH_SOURCE[0]}")
+SOURCE_DIR=$(dirname "$SOURCE_DIR")
+cd "${SOURCE_DIR}/.."
+
+rustup component add --toolchain nightly rust-src
+for features in "" defmt; do
+    cargo +nightly build -Z build-std=core --target thumbv7em-none-eabihf \
+        --no-default-features --features "$features"
+done
+for features in alloc alloc,defmt; do
+    cargo +nightly build -Z build-std=core,alloc --target thumbv7em-none-eabihf \
+        --no-default-features --features "$features"
+done
@@ -1,6 +1,10 @@
 //! Error handling.
 
-use std::{io, result, str, string};
+#[cfg(feature = "alloc")]
+use alloc::string::{self, String};
+use core::{result, str};
+
+use crate::io;
 
 use crate::protocol::{frame::coding::Data, Message};
 #[cfg(feature = "handshake")]
@@ -12,6 +16,7 @@ pub type Result<T, E = Error> = result::Result<T, E>;
 
 /// Possible WebSocket errors.
 #[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     /// WebSocket connection closed normally. This informs you of the close.
     /// It's not an error as such and nothing wrong happened.
@@ -38,7 +43,10 @@ pub enum Error {
     /// Input-output error. Apart from WouldBlock, these are generally errors with the
     /// underlying connection and you should probably consider them fatal.
     #[error("IO error: {0}")]
-    Io(#[from] io::Error),
+    Io(
+        #[cfg_attr(all(feature = "defmt", feature = "std"), defmt(Display2Format))]
+        io::Error,
+    ),
     /// TLS error.
     ///
     /// Note that this error variant is enabled unconditionally even if no TLS feature is enabled,
@@ -55,24 +63,66 @@ pub enum Error {
     Protocol(#[from] ProtocolError),
     /// Message write buffer is full.
     #[error("Write buffer is full")]
-    WriteBufferFull(Message),
+    WriteBufferFull(Message<'static>),
     /// UTF coding error.
     #[error("UTF-8 encoding error")]
     Utf8,
-    /// Attack attempt detected.
+    /// Attack attempt detected, e.g. a handshake trickling in too many or too small packets,
+    /// see [`HandshakeConfig::max_packets`].
+    ///
+    /// [`HandshakeConfig::max_packets`]: crate::handshake::HandshakeConfig::max_packets
     #[error("Attack attempt detected")]
     AttackAttempt,
+    /// The handshake did not complete within [`HandshakeConfig::timeout`].
+    ///
+    /// [`HandshakeConfig::timeout`]: crate::handshake::HandshakeConfig::timeout
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
     /// Invalid URL.
     #[error("URL error: {0}")]
     Url(#[from] UrlError),
+    /// Failed to open a tunnel through the configured proxy.
+    #[error("Proxy error: {0}")]
+    Proxy(#[from] ProxyError),
     /// HTTP error.
     #[error("HTTP error: {}", .0.status())]
     #[cfg(feature = "handshake")]
-    Http(Response<Option<Vec<u8>>>),
+    Http(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] Response<Option<Vec<u8>>>),
     /// HTTP format error.
     #[error("HTTP format error: {0}")]
     #[cfg(feature = "handshake")]
-    HttpFormat(#[from] http::Error),
+    HttpFormat(#[from] #[cfg_attr(feature = "defmt", defmt(Display2Format))] http::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        // A failed `native-tls` handshake resumed by a read or write of `NativeTlsAccept`.
+        #[cfg(feature = "native-tls")]
+        if err.get_ref().is_some_and(|inner| inner.is::<native_tls_crate::Error>()) {
+            let inner = err.into_inner().expect("Bug: no inner error").downcast();
+            return Error::Tls(TlsError::Native(*inner.expect("Bug: not a native-tls error")));
+        }
+        // A certificate rejected by the pins or the verifier of a `rustls` connector.
+        #[cfg(feature = "__rustls-tls")]
+        if let Some(rejected) = rejected_certificate(&err) {
+            return Error::Tls(rejected);
+        }
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "__rustls-tls")]
+fn rejected_certificate(err: &io::Error) -> Option<TlsError> {
+    match err.get_ref()?.downcast_ref::<rustls::Error>()? {
+        rustls::Error::InvalidCertificate(rustls::CertificateError::Other(other)) => {
+            match other.downcast_ref::<TlsError>()? {
+                TlsError::PinMismatch => Some(TlsError::PinMismatch),
+                TlsError::CertificateRejected => Some(TlsError::CertificateRejected),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
 }
 
 impl From<str::Utf8Error> for Error {
@@ -81,6 +131,7 @@ impl From<str::Utf8Error> for Error {
     }
 }
 
+#[cfg(feature = "alloc")]
 impl From<string::FromUtf8Error> for Error {
     fn from(_: string::FromUtf8Error) -> Self {
         Error::Utf8
@@ -134,10 +185,19 @@ impl From<httparse::Error> for Error {
 
 /// Indicates the specific type/cause of a capacity error.
 #[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CapacityError {
     /// Too many headers provided (see [`httparse::Error::TooManyHeaders`]).
     #[error("Too many headers")]
     TooManyHeaders,
+    /// The head of the handshake request or response is bigger than allowed.
+    #[error("Handshake too long: {size} > {max_size}")]
+    HandshakeTooLong {
+        /// The number of bytes received so far.
+        size: usize,
+        /// The maximum allowed size.
+        max_size: usize,
+    },
     /// Received header is too long.
     /// Message is bigger than the maximum allowed size.
     #[error("Message too long: {size} > {max_size}")]
@@ -152,6 +212,7 @@ pub enum CapacityError {
 /// Indicates the specific type/cause of a protocol error.
 #[allow(missing_copy_implementations)]
 #[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ProtocolError {
     /// Use of the wrong HTTP method (the WebSocket protocol requires the GET method be used).
     #[error("Unsupported HTTP method used - only GET is allowed")]
@@ -168,12 +229,21 @@ pub enum ProtocolError {
     /// Missing `Sec-WebSocket-Version: 13` HTTP header.
     #[error("No \"Sec-WebSocket-Version: 13\" header")]
     MissingSecWebSocketVersionHeader,
+    /// An HTTP/2 extended CONNECT request uses another method or HTTP version.
+    #[error("Extended CONNECT requires the CONNECT method over HTTP/2")]
+    ExtendedConnectRequired,
+    /// Missing `:protocol: websocket` pseudo-header of an HTTP/2 extended CONNECT request.
+    #[error("No \":protocol: websocket\" pseudo-header")]
+    MissingProtocolPseudoHeader,
     /// Missing `Sec-WebSocket-Key` HTTP header.
     #[error("No \"Sec-WebSocket-Key\" header")]
     MissingSecWebSocketKey,
     /// The `Sec-WebSocket-Accept` header is either not present or does not specify the correct key value.
     #[error("Key mismatch in \"Sec-WebSocket-Accept\" header")]
     SecWebSocketAcceptKeyMismatch,
+    /// The `Sec-WebSocket-Protocol` header sent by the server is invalid.
+    #[error("SubProtocol error: {0}")]
+    SecWebSocketSubProtocolError(SubProtocolError),
     /// Garbage data encountered after client request.
     #[error("Junk after client request")]
     JunkAfterRequest,
@@ -183,14 +253,14 @@ pub enum ProtocolError {
     /// Invalid header is passed. Or the header is missing in the request. Or not present at all. Check the request that you pass.
     #[error("Missing, duplicated or incorrect header {0}")]
     #[cfg(feature = "handshake")]
-    InvalidHeader(HeaderName),
+    InvalidHeader(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] HeaderName),
     /// No more data while still performing handshake.
     #[error("Handshake not finished")]
     HandshakeIncomplete,
     /// Wrapper around a [`httparse::Error`] value.
     #[error("httparse error: {0}")]
     #[cfg(feature = "handshake")]
-    HttparseError(#[from] httparse::Error),
+    HttparseError(#[from] #[cfg_attr(feature = "defmt", defmt(Display2Format))] httparse::Error),
     /// Not allowed to send after having sent a closing frame.
     #[error("Sending after closing is not allowed")]
     SendAfterClosing,
@@ -233,10 +303,28 @@ pub enum ProtocolError {
     /// The payload for the closing frame is invalid.
     #[error("Invalid close sequence")]
     InvalidCloseSequence,
+    /// A client has to mask its frames, but no mask generator is configured.
+    #[error("No mask generator configured, see WebSocketConfig::mask_generator")]
+    MissingMaskGenerator,
+}
+
+/// Indicates the specific type/cause of a subprotocol header error.
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SubProtocolError {
+    /// The server sent a subprotocol to a client handshake request but none was requested.
+    #[error("Server sent a subprotocol but none was requested")]
+    ServerSentSubProtocolNoneRequested,
+    /// The server sent an invalid subprotocol to a client handshake request, i.e. one that was
+    /// not offered by the client or more than one value.
+    #[error("Server sent an invalid subprotocol")]
+    InvalidSubProtocol,
 }
 
 /// Indicates the specific type/cause of URL error.
+#[allow(missing_copy_implementations)]
 #[derive(Error, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum UrlError {
     /// TLS is used despite not being compiled with the TLS feature enabled.
     #[error("TLS support not compiled in")]
@@ -246,8 +334,10 @@ pub enum UrlError {
     NoHostName,
     /// Failed to connect with this URL.
     #[error("Unable to connect to {0}")]
+    #[cfg(feature = "alloc")]
     UnableToConnect(String),
-    /// Unsupported URL scheme used (only `ws://` or `wss://` may be used).
+    /// Unsupported URL scheme used (only `ws://` or `wss://` may be used, and `ws+unix://` with
+    /// `connect_unix`).
     #[error("URL scheme not supported")]
     UnsupportedUrlScheme,
     /// The URL host name, though included, is empty.
@@ -256,6 +346,43 @@ pub enum UrlError {
     /// The URL does not include a path/query.
     #[error("No path/query in URL")]
     NoPathOrQuery,
+    /// The `ws+unix://` URL does not include a socket path.
+    #[error("No socket path in the URL")]
+    NoSocketPath,
+    /// The `Location` of a redirect is not a valid URL.
+    #[error("Invalid redirect location: {0}")]
+    #[cfg(feature = "alloc")]
+    InvalidRedirectLocation(String),
+    /// A redirect from `wss://` to `ws://` was refused.
+    #[error("Refusing insecure redirect to {0}")]
+    #[cfg(feature = "alloc")]
+    InsecureRedirect(String),
+}
+
+/// Proxy errors.
+#[allow(missing_copy_implementations)]
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProxyError {
+    /// The proxy URL could not be parsed or uses an unsupported scheme.
+    #[error("Invalid proxy URL: {0}")]
+    #[cfg(feature = "alloc")]
+    InvalidUrl(String),
+    /// The HTTP proxy refused the `CONNECT` request.
+    #[error("Proxy responded with HTTP status {0}")]
+    HttpStatus(u16),
+    /// The proxy sent a response that could not be understood.
+    #[error("Malformed proxy response")]
+    MalformedResponse,
+    /// The SOCKS5 proxy accepts none of the offered authentication methods.
+    #[error("No acceptable SOCKS5 authentication method")]
+    Socks5NoAcceptableAuth,
+    /// The SOCKS5 proxy rejected the credentials.
+    #[error("SOCKS5 authentication failed")]
+    Socks5AuthFailed,
+    /// The SOCKS5 proxy failed to connect to the target, contains the reply code.
+    #[error("SOCKS5 connect failed with reply code {0}")]
+    Socks5ConnectFailed(u8),
 }
 
 /// TLS errors.
@@ -264,18 +391,35 @@ pub enum UrlError {
 /// be `Native`, as another crate in the dependency graph may enable native TLS support.
 #[allow(missing_copy_implementations)]
 #[derive(Error, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum TlsError {
     /// Native TLS error.
     #[cfg(feature = "native-tls")]
     #[error("native-tls error: {0}")]
-    Native(#[from] native_tls_crate::Error),
+    Native(#[from] #[cfg_attr(feature = "defmt", defmt(Display2Format))] native_tls_crate::Error),
     /// Rustls error.
     #[cfg(feature = "__rustls-tls")]
     #[error("rustls error: {0}")]
-    Rustls(#[from] rustls::Error),
+    Rustls(#[from] #[cfg_attr(feature = "defmt", defmt(Display2Format))] rustls::Error),
     /// DNS name resolution error.
     #[cfg(feature = "__rustls-tls")]
     #[error("Invalid DNS name")]
     InvalidDnsName,
+    /// The certificate of the server matches none of the pinned SPKI fingerprints.
+    #[cfg(feature = "__rustls-tls")]
+    #[error("Server certificate does not match any pinned public key")]
+    PinMismatch,
+    /// The certificate of the server has been rejected by the verifier callback.
+    #[cfg(feature = "__rustls-tls")]
+    #[error("Server certificate rejected by the verifier")]
+    CertificateRejected,
+    /// A certificate or private key in the TLS options could not be parsed, or only one of
+    /// client certificate and key has been given.
+    #[error("Invalid certificate or private key: {0}")]
+    InvalidPem(&'static str),
+    /// An ALPN protocol in the TLS options is not valid UTF-8, which `native-tls` requires.
+    #[cfg(feature = "native-tls")]
+    #[error("ALPN protocol is not valid UTF-8")]
+    InvalidAlpnProtocol,
 }
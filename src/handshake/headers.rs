@@ -6,7 +6,9 @@ use httparse::Status;
 use super::machine::TryParse;
 use crate::error::Result;
 
-/// Limit for the number of header lines.
+/// Default limit for the number of header lines, see [`HandshakeConfig::max_headers`].
+///
+/// [`HandshakeConfig::max_headers`]: super::HandshakeConfig::max_headers
 pub const MAX_HEADERS: usize = 124;
 
 /// Trait to convert raw objects into HTTP parseables.
@@ -28,12 +30,42 @@ impl<'b: 'h, 'h> FromHttparse<&'b [httparse::Header<'h>]> for HeaderMap {
         Ok(headers)
     }
 }
-impl TryParse for HeaderMap {
-    fn try_parse(buf: &[u8]) -> Result<Option<(usize, Self)>> {
+
+/// Run `f` with room for `max_headers` parsed headers.
+///
+/// Limits up to [`MAX_HEADERS`] use a stack array, so parsing a handshake with the default
+/// config does not allocate on each read attempt.
+pub(crate) fn with_header_buffer<'b, R>(
+    max_headers: usize,
+    f: impl FnOnce(&mut [httparse::Header<'b>]) -> R,
+) -> R {
+    if max_headers <= MAX_HEADERS {
         let mut hbuffer = [httparse::EMPTY_HEADER; MAX_HEADERS];
-        Ok(match httparse::parse_headers(buf, &mut hbuffer)? {
-            Status::Partial => None,
-            Status::Complete((size, hdr)) => Some((size, HeaderMap::from_httparse(hdr)?)),
+        f(&mut hbuffer[..max_headers])
+    } else {
+        f(&mut vec![httparse::EMPTY_HEADER; max_headers])
+    }
+}
+
+/// Collect the values of all `Sec-WebSocket-Protocol` headers.
+///
+/// The header may be repeated and each occurrence may carry a comma-separated list of tokens,
+/// the result is the flattened list in the order of appearance.
+pub(crate) fn subprotocols(headers: &HeaderMap) -> Result<Vec<&str>> {
+    let mut protocols = Vec::new();
+    for value in headers.get_all("Sec-WebSocket-Protocol") {
+        protocols.extend(value.to_str()?.split(',').map(str::trim).filter(|p| !p.is_empty()));
+    }
+    Ok(protocols)
+}
+
+impl TryParse for HeaderMap {
+    fn try_parse(buf: &[u8], max_headers: usize) -> Result<Option<(usize, Self)>> {
+        with_header_buffer(max_headers, |hbuffer| {
+            Ok(match httparse::parse_headers(buf, hbuffer)? {
+                Status::Partial => None,
+                Status::Complete((size, hdr)) => Some((size, HeaderMap::from_httparse(hdr)?)),
+            })
         })
     }
 }
@@ -41,7 +73,7 @@ impl TryParse for HeaderMap {
 #[cfg(test)]
 mod tests {
 
-    use super::{super::machine::TryParse, HeaderMap};
+    use super::{super::machine::TryParse, subprotocols, HeaderMap, MAX_HEADERS};
 
     #[test]
     fn headers() {
@@ -49,7 +81,7 @@ mod tests {
              Connection: Upgrade\r\n\
              Upgrade: websocket\r\n\
              \r\n";
-        let (_, hdr) = HeaderMap::try_parse(DATA).unwrap().unwrap();
+        let (_, hdr) = HeaderMap::try_parse(DATA, MAX_HEADERS).unwrap().unwrap();
         assert_eq!(hdr.get("Host").unwrap(), &b"foo.com"[..]);
         assert_eq!(hdr.get("Upgrade").unwrap(), &b"websocket"[..]);
         assert_eq!(hdr.get("Connection").unwrap(), &b"Upgrade"[..]);
@@ -63,7 +95,7 @@ mod tests {
               Sec-WebSocket-ExtenSIONS: permessage-unknown\r\n\
               Upgrade: websocket\r\n\
               \r\n";
-        let (_, hdr) = HeaderMap::try_parse(DATA).unwrap().unwrap();
+        let (_, hdr) = HeaderMap::try_parse(DATA, MAX_HEADERS).unwrap().unwrap();
         let mut iter = hdr.get_all("Sec-WebSocket-Extensions").iter();
         assert_eq!(iter.next().unwrap(), &b"permessage-deflate"[..]);
         assert_eq!(iter.next().unwrap(), &b"permessage-unknown"[..]);
@@ -75,7 +107,31 @@ mod tests {
         const DATA: &[u8] = b"Host: foo.com\r\n\
               Connection: Upgrade\r\n\
               Upgrade: websocket\r\n";
-        let hdr = HeaderMap::try_parse(DATA).unwrap();
+        let hdr = HeaderMap::try_parse(DATA, MAX_HEADERS).unwrap();
         assert!(hdr.is_none());
     }
+
+    #[test]
+    fn headers_limit() {
+        const DATA: &[u8] = b"Host: foo.com\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              \r\n";
+        assert!(HeaderMap::try_parse(DATA, 2).is_err());
+        let (_, hdr) = HeaderMap::try_parse(DATA, 3).unwrap().unwrap();
+        assert_eq!(hdr.len(), 3);
+        let (_, hdr) = HeaderMap::try_parse(DATA, MAX_HEADERS + 1).unwrap().unwrap();
+        assert_eq!(hdr.len(), 3);
+    }
+
+    #[test]
+    fn subprotocols_list() {
+        const DATA: &[u8] = b"Host: foo.com\r\n\
+              Sec-WebSocket-Protocol: chat, superchat\r\n\
+              Sec-WebSocket-Protocol: v2.json\r\n\
+              \r\n";
+        let (_, hdr) = HeaderMap::try_parse(DATA, MAX_HEADERS).unwrap().unwrap();
+        assert_eq!(subprotocols(&hdr).unwrap(), vec!["chat", "superchat", "v2.json"]);
+        assert!(subprotocols(&HeaderMap::new()).unwrap().is_empty());
+    }
 }
@@ -4,12 +4,16 @@ use bytes::Buf;
 use log::*;
 use std::io::{Cursor, Read, Write};
 
+use super::HandshakeConfig;
 use crate::{
-    error::{Error, ProtocolError, Result},
+    error::{CapacityError, Error, ProtocolError, Result},
+    storage::U8RingBuffer,
     util::NonBlockingResult,
-    ReadBuffer,
 };
 
+/// Initial size of the read buffer, it grows up to [`HandshakeConfig::max_bytes`] as needed.
+const INITIAL_BUFFER_SIZE: usize = 4096;
+
 /// A generic handshake state machine.
 #[derive(Debug)]
 pub struct HandshakeMachine<Stream> {
@@ -20,7 +24,12 @@ pub struct HandshakeMachine<Stream> {
 impl<Stream> HandshakeMachine<Stream> {
     /// Start reading data from the peer.
     pub fn start_read(stream: Stream) -> Self {
-        Self { stream, state: HandshakeState::Reading(ReadBuffer::new(), AttackCheck::new()) }
+        Self::start_read_with_config(stream, HandshakeConfig::default())
+    }
+    /// Start reading data from the peer, enforcing the limits of the given configuration.
+    pub fn start_read_with_config(stream: Stream, config: HandshakeConfig) -> Self {
+        let state = HandshakeState::Reading(U8RingBuffer::empty(), AttackCheck::new(config));
+        Self { stream, state }
     }
     /// Start writing data to the peer.
     pub fn start_write<D: Into<Vec<u8>>>(stream: Stream, data: D) -> Self {
@@ -42,6 +51,16 @@ impl<Stream: Read + Write> HandshakeMachine<Stream> {
         trace!("Doing handshake round.");
         match self.state {
             HandshakeState::Reading(mut buf, mut attack_check) => {
+                if buf.0.is_full() {
+                    let (size, max_size) = (buf.0.len(), attack_check.config.max_bytes);
+                    if size >= max_size {
+                        return Err(Error::Capacity(CapacityError::HandshakeTooLong {
+                            size,
+                            max_size,
+                        }));
+                    }
+                    buf.grow((size * 2).clamp(INITIAL_BUFFER_SIZE.min(max_size), max_size));
+                }
                 let read = buf.read_from(&mut self.stream).no_block()?;
                 match read {
                     Some(0) => Err(Error::Protocol(ProtocolError::HandshakeIncomplete)),
@@ -50,12 +69,17 @@ impl<Stream: Read + Write> HandshakeMachine<Stream> {
                         // TODO: this is slow for big headers with too many small packets.
                         // The parser has to be reworked in order to work on streams instead
                         // of buffers.
-                        Ok(if let Some((size, obj)) = Obj::try_parse(Buf::chunk(&buf))? {
-                            buf.advance(size);
+                        let max_headers = attack_check.config.max_headers;
+                        // Nothing is consumed before the head is parsed, so it never wraps.
+                        let head = buf.0.peek_contiguous(buf.0.len());
+                        let parsed = Obj::try_parse(&head, max_headers)?;
+                        drop(head);
+                        Ok(if let Some((size, obj)) = parsed {
+                            buf.0.consume(size);
                             RoundResult::StageFinished(StageResult::DoneReading {
                                 result: obj,
                                 stream: self.stream,
-                                tail: buf.into_vec(),
+                                tail: buf,
                             })
                         } else {
                             RoundResult::Incomplete(HandshakeMachine {
@@ -108,24 +132,34 @@ pub enum RoundResult<Obj, Stream> {
 /// The result of the stage.
 #[derive(Debug)]
 pub enum StageResult<Obj, Stream> {
-    /// Reading round finished.
+    /// Reading round finished. The `tail` holds the data read past the end of the head.
     #[allow(missing_docs)]
-    DoneReading { result: Obj, stream: Stream, tail: Vec<u8> },
+    DoneReading { result: Obj, stream: Stream, tail: U8RingBuffer<'static> },
     /// Writing round finished.
     DoneWriting(Stream),
 }
 
+impl<Obj, Stream> StageResult<Obj, Stream> {
+    /// Returns a mutable reference to the stream.
+    pub(crate) fn stream_mut(&mut self) -> &mut Stream {
+        match self {
+            StageResult::DoneReading { stream, .. } | StageResult::DoneWriting(stream) => stream,
+        }
+    }
+}
+
 /// The parseable object.
 pub trait TryParse: Sized {
-    /// Return Ok(None) if incomplete, Err on syntax error.
-    fn try_parse(data: &[u8]) -> Result<Option<(usize, Self)>>;
+    /// Return Ok(None) if incomplete, Err on syntax error or if there are more than
+    /// `max_headers` headers.
+    fn try_parse(data: &[u8], max_headers: usize) -> Result<Option<(usize, Self)>>;
 }
 
 /// The handshake state.
 #[derive(Debug)]
 enum HandshakeState {
     /// Reading data from the peer.
-    Reading(ReadBuffer, AttackCheck),
+    Reading(U8RingBuffer<'static>, AttackCheck),
     /// Sending data to the peer.
     Writing(Cursor<Vec<u8>>),
 }
@@ -134,6 +168,8 @@ enum HandshakeState {
 /// and reject valid but useless headers.
 #[derive(Debug)]
 pub(crate) struct AttackCheck {
+    /// The limits to enforce.
+    config: HandshakeConfig,
     /// Number of HTTP header successful reads (TCP packets).
     number_of_packets: usize,
     /// Total number of bytes in HTTP header.
@@ -142,8 +178,8 @@ pub(crate) struct AttackCheck {
 
 impl AttackCheck {
     /// Initialize attack checking for incoming buffer.
-    fn new() -> Self {
-        Self { number_of_packets: 0, number_of_bytes: 0 }
+    fn new(config: HandshakeConfig) -> Self {
+        Self { config, number_of_packets: 0, number_of_bytes: 0 }
     }
 
     /// Check the size of an incoming packet. To be called immediately after `read()`
@@ -152,27 +188,147 @@ impl AttackCheck {
         self.number_of_packets += 1;
         self.number_of_bytes += size;
 
-        // TODO: these values are hardcoded. Instead of making them configurable,
-        // rework the way HTTP header is parsed to remove this check at all.
-        const MAX_BYTES: usize = 65536;
-        const MAX_PACKETS: usize = 512;
-        const MIN_PACKET_SIZE: usize = 128;
-        const MIN_PACKET_CHECK_THRESHOLD: usize = 64;
+        let config = &self.config;
 
-        if self.number_of_bytes > MAX_BYTES {
+        if self.number_of_bytes > config.max_bytes {
+            return Err(Error::Capacity(CapacityError::HandshakeTooLong {
+                size: self.number_of_bytes,
+                max_size: config.max_bytes,
+            }));
+        }
+
+        if self.number_of_packets > config.max_packets {
+            debug!("Handshake split into more than {} packets", config.max_packets);
             return Err(Error::AttackAttempt);
         }
 
-        if self.number_of_packets > MAX_PACKETS {
+        if self.number_of_packets > config.min_packet_check_threshold
+            && self.number_of_packets * config.min_packet_size > self.number_of_bytes
+        {
+            debug!(
+                "Handshake packets too small: {} bytes in {} packets",
+                self.number_of_bytes, self.number_of_packets
+            );
             return Err(Error::AttackAttempt);
         }
 
-        if self.number_of_packets > MIN_PACKET_CHECK_THRESHOLD {
-            if self.number_of_packets * MIN_PACKET_SIZE > self.number_of_bytes {
-                return Err(Error::AttackAttempt);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use http::HeaderMap;
+
+    use super::{HandshakeMachine, RoundResult, StageResult};
+    use crate::{
+        error::{CapacityError, Error, Result},
+        handshake::HandshakeConfig,
+    };
+
+    /// Hands out the data in chunks of `chunk` bytes per read.
+    struct Trickle {
+        data: Vec<u8>,
+        chunk: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let size = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..size].copy_from_slice(&self.data[..size]);
+            self.data.drain(..size);
+            Ok(size)
+        }
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn read_headers(headers: usize, chunk: usize, config: HandshakeConfig) -> Result<HeaderMap> {
+        let mut data: Vec<u8> =
+            (0..headers).flat_map(|i| format!("X-Header-{}: value\r\n", i).into_bytes()).collect();
+        data.extend_from_slice(b"\r\n");
+        let mut machine =
+            HandshakeMachine::start_read_with_config(Trickle { data, chunk }, config);
+        loop {
+            machine = match machine.single_round::<HeaderMap>()? {
+                RoundResult::WouldBlock(m) | RoundResult::Incomplete(m) => m,
+                RoundResult::StageFinished(StageResult::DoneReading { result, .. }) => {
+                    return Ok(result)
+                }
+                RoundResult::StageFinished(StageResult::DoneWriting(_)) => unreachable!(),
             }
         }
+    }
 
-        Ok(())
+    #[test]
+    fn tail_after_head() {
+        let data = b"X-Header: value\r\n\r\n\x81\x02Hi".to_vec();
+        let machine = HandshakeMachine::start_read(Trickle { data, chunk: 4096 });
+        match machine.single_round::<HeaderMap>().unwrap() {
+            RoundResult::StageFinished(StageResult::DoneReading { result, tail, .. }) => {
+                assert_eq!(result.len(), 1);
+                assert_eq!(tail.to_vec(), b"\x81\x02Hi");
+            }
+            _ => panic!("head not read in one round"),
+        }
+    }
+
+    #[test]
+    fn buffer_grows_to_max_bytes() {
+        // A head larger than the initial buffer, followed by a tail.
+        let mut data: Vec<u8> =
+            (0..400).flat_map(|i| format!("X-Header-{:03}: value\r\n", i).into_bytes()).collect();
+        data.extend_from_slice(b"\r\n\x81\x02Hi");
+        let config = HandshakeConfig { max_headers: 400, ..HandshakeConfig::default() };
+        let mut machine =
+            HandshakeMachine::start_read_with_config(Trickle { data, chunk: 4096 }, config);
+        loop {
+            machine = match machine.single_round::<HeaderMap>().unwrap() {
+                RoundResult::WouldBlock(m) | RoundResult::Incomplete(m) => m,
+                RoundResult::StageFinished(StageResult::DoneReading { result, tail, .. }) => {
+                    assert_eq!(result.len(), 400);
+                    assert_eq!(tail.to_vec(), b"\x81\x02Hi");
+                    assert!(tail.0.capacity() < config.max_bytes);
+                    break;
+                }
+                RoundResult::StageFinished(StageResult::DoneWriting(_)) => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn handshake_limits() {
+        let config = HandshakeConfig::default();
+        assert_eq!(read_headers(100, 4096, config).unwrap().len(), 100);
+        assert!(matches!(
+            read_headers(200, 4096, config),
+            Err(Error::Capacity(CapacityError::TooManyHeaders))
+        ));
+        let config = HandshakeConfig { max_headers: 200, ..config };
+        assert_eq!(read_headers(200, 4096, config).unwrap().len(), 200);
+
+        // Each header line is at least 22 bytes long.
+        let config = HandshakeConfig { max_bytes: 1000, ..config };
+        assert!(matches!(
+            read_headers(100, 4096, config),
+            Err(Error::Capacity(CapacityError::HandshakeTooLong { max_size: 1000, .. }))
+        ));
+
+        let config = HandshakeConfig { max_packets: 10, ..HandshakeConfig::default() };
+        assert!(matches!(read_headers(10, 16, config), Err(Error::AttackAttempt)));
+
+        let config = HandshakeConfig::default();
+        assert!(matches!(read_headers(10, 1, config), Err(Error::AttackAttempt)));
+        let config = HandshakeConfig { min_packet_check_threshold: 1000, ..config };
+        assert_eq!(read_headers(10, 1, config).unwrap().len(), 10);
     }
 }
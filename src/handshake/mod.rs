@@ -7,20 +7,103 @@ pub mod server;
 
 use std::{
     error::Error as ErrorTrait,
-    fmt,
+    fmt, io,
     io::{Read, Write},
+    time::{Duration, Instant},
 };
 
 use sha1::{Digest, Sha1};
 
 use self::machine::{HandshakeMachine, RoundResult, StageResult, TryParse};
-use crate::error::Error;
+use crate::{error::Error, stream::SetTimeout};
+
+/// Limits applied while reading the HTTP request or response of the handshake.
+///
+/// They protect against peers that send huge headers or trickle them in tiny pieces. A head
+/// that is too big fails the handshake with an [`Error::Capacity`] saying which limit was hit,
+/// one that arrives in too many or too small reads with [`Error::AttackAttempt`].
+///
+/// The configuration is taken from [`WebSocketConfig::handshake`], so it applies to all
+/// `accept` and `connect` functions that take a [`WebSocketConfig`].
+///
+/// [`WebSocketConfig::handshake`]: crate::protocol::WebSocketConfig::handshake
+/// [`WebSocketConfig`]: crate::protocol::WebSocketConfig
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakeConfig {
+    /// The maximum size of the request or response head in bytes. The read buffer starts
+    /// small and only grows up to this size for large heads. The default value is 64 KiB.
+    pub max_bytes: usize,
+    /// The maximum number of successful reads (usually TCP packets) of the head.
+    /// The default value is 512.
+    pub max_packets: usize,
+    /// The minimum average size of a read once more than `min_packet_check_threshold` reads
+    /// have been done, which catches slow-loris style attacks. The default value is 128.
+    pub min_packet_size: usize,
+    /// The number of reads after which `min_packet_size` is enforced.
+    /// The default value is 64.
+    pub min_packet_check_threshold: usize,
+    /// The maximum number of header lines.
+    /// The default value is 124.
+    pub max_headers: usize,
+    /// The time allowed for the whole handshake, starting with `start()`. `None` means no limit.
+    /// The default value is `None`.
+    ///
+    /// The deadline is checked before every read and write. On blocking streams a read or
+    /// write only returns at the deadline if the stream has timeouts, see
+    /// [`MidHandshake::with_stream_timeouts`].
+    pub timeout: Option<Duration>,
+    /// Whether the server answers `408 Request Timeout` if the deadline passes before the
+    /// request has been read. Ignored by clients. The default value is `false`.
+    pub send_timeout_response: bool,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        HandshakeConfig {
+            max_bytes: 65536,
+            max_packets: 512,
+            min_packet_size: 128,
+            min_packet_check_threshold: 64,
+            max_headers: headers::MAX_HEADERS,
+            timeout: None,
+            send_timeout_response: false,
+        }
+    }
+}
+
+/// The value of the `:protocol` pseudo-header of an HTTP/2 extended CONNECT request
+/// (RFC 8441).
+///
+/// HTTP/2 implementations keep pseudo-headers out of the header map, `h2` for instance carries
+/// it as `h2::ext::Protocol` in the extensions of the request.
+pub const EXTENDED_CONNECT_PROTOCOL: &str = "websocket";
+
+/// Sets the read and write timeouts of a handshake stream.
+type SetTimeoutFn<Stream> = fn(&mut Stream, Option<Duration>) -> io::Result<()>;
 
 /// A WebSocket handshake.
 #[derive(Debug)]
 pub struct MidHandshake<Role: HandshakeRole> {
     role: Role,
     machine: HandshakeMachine<Role::InternalStream>,
+    deadline: Option<Instant>,
+    set_timeout: Option<SetTimeoutFn<Role::InternalStream>>,
+}
+
+impl<Role: HandshakeRole> MidHandshake<Role>
+where
+    Role::InternalStream: SetTimeout,
+{
+    /// Enforce [`HandshakeConfig::timeout`] on a blocking stream: before each round, the read
+    /// and write timeouts of the stream are set to the time remaining until the deadline, and
+    /// they are removed again once a stage of the handshake has finished.
+    ///
+    /// `accept_unix`, `connect_unix_socket` and connecting to a `ws+unix://` URL do this on
+    /// their own.
+    pub fn with_stream_timeouts(mut self) -> Self {
+        self.set_timeout = Some(<Role::InternalStream as SetTimeout>::set_timeout);
+        self
+    }
 }
 
 impl<Role: HandshakeRole> MidHandshake<Role> {
@@ -38,15 +121,39 @@ impl<Role: HandshakeRole> MidHandshake<Role> {
     pub fn handshake(mut self) -> Result<Role::FinalResult, HandshakeError<Role>> {
         let mut mach = self.machine;
         loop {
+            let now = Instant::now();
+            if self.deadline.is_some_and(|deadline| now >= deadline) {
+                if let Some(response) = self.role.timeout_response() {
+                    // Best effort, the connection is given up anyway.
+                    let stream = mach.get_mut();
+                    let _ = stream.write_all(response).and_then(|()| stream.flush());
+                }
+                return Err(HandshakeError::Failure(Error::HandshakeTimeout));
+            }
+            if let (Some(deadline), Some(set_timeout)) = (self.deadline, self.set_timeout) {
+                set_timeout(mach.get_mut(), Some(deadline - now)).map_err(Error::Io)?;
+            }
             mach = match mach.single_round()? {
+                // The timeout of a blocking stream has expired, fail with `HandshakeTimeout`.
+                RoundResult::WouldBlock(m)
+                    if self.set_timeout.is_some()
+                        && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) =>
+                {
+                    m
+                }
                 RoundResult::WouldBlock(m) => {
                     return Err(HandshakeError::Interrupted(MidHandshake { machine: m, ..self }))
                 }
                 RoundResult::Incomplete(m) => m,
-                RoundResult::StageFinished(s) => match self.role.stage_finished(s)? {
-                    ProcessingResult::Continue(m) => m,
-                    ProcessingResult::Done(result) => return Ok(result),
-                },
+                RoundResult::StageFinished(mut s) => {
+                    if let (Some(_), Some(set_timeout)) = (self.deadline, self.set_timeout) {
+                        set_timeout(s.stream_mut(), None).map_err(Error::Io)?;
+                    }
+                    match self.role.stage_finished(s)? {
+                        ProcessingResult::Continue(m) => m,
+                        ProcessingResult::Done(result) => return Ok(result),
+                    }
+                }
             }
         }
     }
@@ -99,6 +206,10 @@ pub trait HandshakeRole {
         &mut self,
         finish: StageResult<Self::IncomingData, Self::InternalStream>,
     ) -> Result<ProcessingResult<Self::InternalStream, Self::FinalResult>, Error>;
+    #[doc(hidden)]
+    fn timeout_response(&self) -> Option<&'static [u8]> {
+        None
+    }
 }
 
 /// Stage processing result.
@@ -1,22 +1,26 @@
 //! Server handshake machine.
 
 use std::{
+    fmt,
     io::{self, Read, Write},
     marker::PhantomData,
     result::Result as StdResult,
+    sync::Arc,
+    time::Instant,
 };
 
 use http::{
-    response::Builder, HeaderMap, Request as HttpRequest, Response as HttpResponse, StatusCode,
+    response::Builder, uri::Authority, Extensions, HeaderMap, Request as HttpRequest,
+    Response as HttpResponse, StatusCode,
 };
 use httparse::Status;
 use log::*;
 
 use super::{
     derive_accept_key,
-    headers::{FromHttparse, MAX_HEADERS},
+    headers::{subprotocols, with_header_buffer, FromHttparse},
     machine::{HandshakeMachine, StageResult, TryParse},
-    HandshakeRole, MidHandshake, ProcessingResult,
+    HandshakeRole, MidHandshake, ProcessingResult, EXTENDED_CONNECT_PROTOCOL,
 };
 use crate::{
     error::{Error, ProtocolError, Result},
@@ -45,7 +49,7 @@ fn create_parts<T>(request: &HttpRequest<T>) -> Result<Builder> {
         .headers()
         .get("Connection")
         .and_then(|h| h.to_str().ok())
-        .map(|h| h.split(|c| c == ' ' || c == ',').any(|p| p.eq_ignore_ascii_case("Upgrade")))
+        .map(|h| h.split([' ', ',']).any(|p| p.eq_ignore_ascii_case("Upgrade")))
         .unwrap_or(false)
     {
         return Err(Error::Protocol(ProtocolError::MissingConnectionUpgradeHeader));
@@ -93,6 +97,50 @@ pub fn create_response_with_body<T>(
     Ok(create_parts(request)?.body(generate_body())?)
 }
 
+/// Create a `200` response for an HTTP/2 extended CONNECT request (RFC 8441).
+///
+/// The request must use the `CONNECT` method over HTTP/2, `protocol` is the value of its
+/// `:protocol` pseudo-header and must be [`EXTENDED_CONNECT_PROTOCOL`]. With `h2` it is read
+/// from the extensions:
+/// `request.extensions().get::<h2::ext::Protocol>().map(|p| p.as_str())`.
+///
+/// There is no key to accept, the response only confirms the stream. Subprotocol negotiation
+/// works on the response as it does for HTTP/1.1.
+pub fn create_extended_connect_response<T>(
+    request: &HttpRequest<T>,
+    protocol: Option<&str>,
+) -> Result<Response> {
+    if request.method() != http::Method::CONNECT || request.version() != http::Version::HTTP_2 {
+        return Err(Error::Protocol(ProtocolError::ExtendedConnectRequired));
+    }
+
+    if protocol != Some(EXTENDED_CONNECT_PROTOCOL) {
+        return Err(Error::Protocol(ProtocolError::MissingProtocolPseudoHeader));
+    }
+
+    if !request.headers().get("Sec-WebSocket-Version").map(|h| h == "13").unwrap_or(false) {
+        return Err(Error::Protocol(ProtocolError::MissingSecWebSocketVersionHeader));
+    }
+
+    Ok(Response::builder().status(StatusCode::OK).version(http::Version::HTTP_2).body(())?)
+}
+
+/// Build a server WebSocket on an HTTP/2 stream after sending `response` to an extended CONNECT
+/// request.
+///
+/// `stream` carries the DATA frames of the HTTP/2 stream, the subprotocol is taken from the
+/// `Sec-WebSocket-Protocol` header of the response.
+pub fn accept_extended_connect<'a, S: Read + Write, T>(
+    stream: S,
+    response: &HttpResponse<T>,
+    config: Option<WebSocketConfig>,
+) -> Result<WebSocket<'a, S>> {
+    let subprotocol = subprotocols(response.headers())?.first().map(|p| p.to_string());
+    let mut websocket = WebSocket::from_raw_socket(stream, Role::Server, config);
+    websocket.set_subprotocol(subprotocol);
+    Ok(websocket)
+}
+
 /// Write `response` to the stream `w`.
 pub fn write_response<T>(mut w: impl io::Write, response: &HttpResponse<T>) -> Result<()> {
     writeln!(
@@ -112,12 +160,13 @@ pub fn write_response<T>(mut w: impl io::Write, response: &HttpResponse<T>) -> R
 }
 
 impl TryParse for Request {
-    fn try_parse(buf: &[u8]) -> Result<Option<(usize, Self)>> {
-        let mut hbuffer = [httparse::EMPTY_HEADER; MAX_HEADERS];
-        let mut req = httparse::Request::new(&mut hbuffer);
-        Ok(match req.parse(buf)? {
-            Status::Partial => None,
-            Status::Complete(size) => Some((size, Request::from_httparse(req)?)),
+    fn try_parse(buf: &[u8], max_headers: usize) -> Result<Option<(usize, Self)>> {
+        with_header_buffer(max_headers, |hbuffer| {
+            let mut req = httparse::Request::new(hbuffer);
+            Ok(match req.parse(buf)? {
+                Status::Partial => None,
+                Status::Complete(size) => Some((size, Request::from_httparse(req)?)),
+            })
         })
     }
 }
@@ -176,6 +225,67 @@ where
     }
 }
 
+/// The callback trait with access to the stream and to the extensions of the connection.
+///
+/// It is implemented for every [`Callback`] and for closures wrapped into
+/// [`StreamCallbackFn`]. Besides the request, the callback sees the stream the handshake runs
+/// on (e.g. to look up the peer address or the TLS client certificate) and may insert values
+/// into `extensions`, which are then available from [`WebSocket::extensions`], e.g. to pass
+/// the result of an authentication on to the message loop.
+pub trait StreamCallback<S>: Sized {
+    /// Called whenever the server read the request from the client and is ready to reply to it.
+    /// May return additional reply headers.
+    /// Returning an error resulting in rejecting the incoming connection.
+    fn on_request(
+        self,
+        stream: &S,
+        request: &Request,
+        response: Response,
+        extensions: &mut Extensions,
+    ) -> StdResult<Response, ErrorResponse>;
+}
+
+impl<S, C: Callback> StreamCallback<S> for C {
+    fn on_request(
+        self,
+        _stream: &S,
+        request: &Request,
+        response: Response,
+        _extensions: &mut Extensions,
+    ) -> StdResult<Response, ErrorResponse> {
+        Callback::on_request(self, request, response)
+    }
+}
+
+/// Wrapper turning a closure into a [`StreamCallback`].
+#[derive(Clone, Copy, Debug)]
+pub struct StreamCallbackFn<F>(F);
+
+impl<F> StreamCallbackFn<F> {
+    /// Wrap a closure receiving the stream, the request, the response and the extensions.
+    pub fn new<S>(callback: F) -> Self
+    where
+        F: FnOnce(&S, &Request, Response, &mut Extensions) -> StdResult<Response, ErrorResponse>,
+    {
+        StreamCallbackFn(callback)
+    }
+}
+
+impl<S, F> StreamCallback<S> for StreamCallbackFn<F>
+where
+    F: FnOnce(&S, &Request, Response, &mut Extensions) -> StdResult<Response, ErrorResponse>,
+{
+    fn on_request(
+        self,
+        stream: &S,
+        request: &Request,
+        response: Response,
+        extensions: &mut Extensions,
+    ) -> StdResult<Response, ErrorResponse> {
+        (self.0)(stream, request, response, extensions)
+    }
+}
+
 /// Stub for callback that does nothing.
 #[derive(Clone, Copy, Debug)]
 pub struct NoCallback;
@@ -190,6 +300,261 @@ impl Callback for NoCallback {
     }
 }
 
+/// The subprotocol selector trait.
+///
+/// A selector is given the subprotocols offered by the client in the `Sec-WebSocket-Protocol`
+/// headers (in the client's order of preference) and picks the one the server will speak.
+/// Returning `None` accepts the connection without a subprotocol.
+///
+/// It is implemented for closures and for lists of protocols supported by the server
+/// (`Vec<String>`, `&[&str]`, `[&str; N]`), in which case the first protocol of the list which
+/// was offered by the client is selected.
+pub trait SubprotocolSelector: Sized {
+    /// Pick one of the `offered` subprotocols for the `request`.
+    fn select_subprotocol(self, request: &Request, offered: &[&str]) -> Option<String>;
+}
+
+impl<F> SubprotocolSelector for F
+where
+    F: FnOnce(&Request, &[&str]) -> Option<String>,
+{
+    fn select_subprotocol(self, request: &Request, offered: &[&str]) -> Option<String> {
+        self(request, offered)
+    }
+}
+
+impl SubprotocolSelector for Vec<String> {
+    fn select_subprotocol(self, _request: &Request, offered: &[&str]) -> Option<String> {
+        self.into_iter().find(|p| offered.contains(&p.as_str()))
+    }
+}
+
+impl<'a> SubprotocolSelector for &'a [&'a str] {
+    fn select_subprotocol(self, _request: &Request, offered: &[&str]) -> Option<String> {
+        self.iter().find(|p| offered.contains(p)).map(|p| p.to_string())
+    }
+}
+
+impl<const N: usize> SubprotocolSelector for [&str; N] {
+    fn select_subprotocol(self, request: &Request, offered: &[&str]) -> Option<String> {
+        self[..].select_subprotocol(request, offered)
+    }
+}
+
+/// Callback which negotiates a subprotocol before handing over to another callback.
+///
+/// The selected protocol is put into the `Sec-WebSocket-Protocol` header of the response,
+/// the inner callback sees it and may still change or reject the response. A protocol the
+/// client did not offer is never selected.
+#[derive(Clone, Copy, Debug)]
+pub struct SubprotocolCallback<P, C> {
+    selector: P,
+    callback: C,
+}
+
+impl<P: SubprotocolSelector, C: Callback> SubprotocolCallback<P, C> {
+    /// Negotiate the subprotocol with `selector`, then call `callback`.
+    pub fn new(selector: P, callback: C) -> Self {
+        SubprotocolCallback { selector, callback }
+    }
+}
+
+impl<P: SubprotocolSelector> SubprotocolCallback<P, NoCallback> {
+    /// Negotiate the subprotocol with `selector` without any further processing.
+    pub fn with_selector(selector: P) -> Self {
+        SubprotocolCallback { selector, callback: NoCallback }
+    }
+}
+
+impl<P: SubprotocolSelector, C: Callback> Callback for SubprotocolCallback<P, C> {
+    fn on_request(
+        self,
+        request: &Request,
+        mut response: Response,
+    ) -> StdResult<Response, ErrorResponse> {
+        let offered = subprotocols(request.headers()).map_err(|_| {
+            let mut error = ErrorResponse::new(Some("Invalid Sec-WebSocket-Protocol".into()));
+            *error.status_mut() = StatusCode::BAD_REQUEST;
+            error
+        })?;
+
+        let selected = self
+            .selector
+            .select_subprotocol(request, &offered)
+            .filter(|p| offered.contains(&p.as_str()));
+        if let Some(protocol) = selected {
+            debug!("Selected subprotocol {:?}", protocol);
+            let value = protocol.parse().expect("Bug: offered subprotocol is a valid header");
+            response.headers_mut().insert("Sec-WebSocket-Protocol", value);
+        }
+
+        self.callback.on_request(request, response)
+    }
+}
+
+/// Origins a server accepts WebSocket connections from.
+///
+/// Browsers send the `Origin` of the page opening a WebSocket, checking it protects against
+/// cross-site WebSocket hijacking. Origins are compared ASCII case-insensitively.
+#[derive(Clone, Default)]
+pub enum OriginPolicy {
+    /// Accept any origin.
+    #[default]
+    Any,
+    /// Accept the listed origins, e.g. `https://example.com` or `http://localhost:8080`.
+    Exact(Vec<String>),
+    /// Accept the subdomains of a domain for a scheme, built with
+    /// [`OriginPolicy::wildcard_subdomain`].
+    WildcardSubdomain {
+        /// Scheme of the origin, e.g. `https`.
+        scheme: String,
+        /// Domain, optionally with a port, e.g. `example.com`.
+        domain: String,
+    },
+    /// Accept the origins for which the predicate returns `true`.
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl OriginPolicy {
+    /// Accept the subdomains matched by a pattern such as `https://*.example.com`.
+    ///
+    /// The domain itself is not matched, list it separately if needed. Returns `None` if
+    /// the pattern has no `<scheme>://*.` prefix.
+    pub fn wildcard_subdomain(pattern: &str) -> Option<Self> {
+        let split = pattern.find("://*.")?;
+        let (scheme, domain) = (&pattern[..split], &pattern[split + 5..]);
+        if scheme.is_empty() || domain.is_empty() {
+            return None;
+        }
+        Some(OriginPolicy::WildcardSubdomain { scheme: scheme.into(), domain: domain.into() })
+    }
+
+    /// Check whether `origin` is accepted.
+    pub fn allows(&self, origin: &str) -> bool {
+        match self {
+            OriginPolicy::Any => true,
+            OriginPolicy::Exact(origins) => origins.iter().any(|o| o.eq_ignore_ascii_case(origin)),
+            OriginPolicy::WildcardSubdomain { scheme, domain } => {
+                let origin = origin.to_ascii_lowercase();
+                origin
+                    .strip_prefix(&format!("{}://", scheme.to_ascii_lowercase()))
+                    .and_then(|authority| {
+                        authority.strip_suffix(&format!(".{}", domain.to_ascii_lowercase()))
+                    })
+                    .map(|subdomain| !subdomain.is_empty())
+                    .unwrap_or(false)
+            }
+            OriginPolicy::Predicate(predicate) => predicate(origin),
+        }
+    }
+}
+
+impl fmt::Debug for OriginPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OriginPolicy::Any => f.write_str("Any"),
+            OriginPolicy::Exact(origins) => f.debug_tuple("Exact").field(origins).finish(),
+            OriginPolicy::WildcardSubdomain { scheme, domain } => f
+                .debug_struct("WildcardSubdomain")
+                .field("scheme", scheme)
+                .field("domain", domain)
+                .finish(),
+            OriginPolicy::Predicate(_) => f.write_str("Predicate(..)"),
+        }
+    }
+}
+
+/// Callback which checks the `Origin` and `Host` headers before handing over to another callback.
+///
+/// A request from a disallowed origin or to a disallowed host is rejected with
+/// `403 Forbidden` and the inner callback is not called. Requests without an `Origin` header
+/// do not come from a browser and are accepted unless [`OriginCallback::require_origin`] is
+/// set.
+#[derive(Clone, Debug)]
+pub struct OriginCallback<C> {
+    origins: OriginPolicy,
+    hosts: Vec<String>,
+    require_origin: bool,
+    callback: C,
+}
+
+impl<C: Callback> OriginCallback<C> {
+    /// Check the origin against `origins`, then call `callback`.
+    pub fn new(origins: OriginPolicy, callback: C) -> Self {
+        OriginCallback { origins, hosts: Vec::new(), require_origin: false, callback }
+    }
+
+    /// Only accept requests whose `Host` header names one of `hosts`.
+    ///
+    /// An entry without a port matches the host on any port, e.g. `example.com` matches
+    /// `example.com:8080`. An empty list accepts any host.
+    pub fn allow_hosts<I: IntoIterator<Item = T>, T: Into<String>>(mut self, hosts: I) -> Self {
+        self.hosts = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Reject requests without an `Origin` header.
+    pub fn require_origin(mut self, require: bool) -> Self {
+        self.require_origin = require;
+        self
+    }
+
+    fn allows_host(&self, request: &Request) -> bool {
+        if self.hosts.is_empty() {
+            return true;
+        }
+        let host = match request.headers().get("Host").and_then(|h| h.to_str().ok()) {
+            Some(host) => host,
+            None => return false,
+        };
+        let name = host.parse::<Authority>().map(|a| a.host().to_owned()).ok();
+        self.hosts.iter().any(|allowed| {
+            allowed.eq_ignore_ascii_case(host)
+                || name.as_ref().map(|n| allowed.eq_ignore_ascii_case(n)).unwrap_or(false)
+        })
+    }
+
+    fn allows_origin(&self, request: &Request) -> bool {
+        match request.headers().get("Origin").map(|h| h.to_str()) {
+            Some(Ok(origin)) => self.origins.allows(origin),
+            Some(Err(_)) => false,
+            None => !self.require_origin,
+        }
+    }
+}
+
+impl OriginCallback<NoCallback> {
+    /// Check the origin against `origins` without any further processing.
+    pub fn with_policy(origins: OriginPolicy) -> Self {
+        OriginCallback::new(origins, NoCallback)
+    }
+}
+
+impl<C: Callback> Callback for OriginCallback<C> {
+    fn on_request(
+        self,
+        request: &Request,
+        response: Response,
+    ) -> StdResult<Response, ErrorResponse> {
+        let rejection = if !self.allows_host(request) {
+            Some("Host not allowed")
+        } else if !self.allows_origin(request) {
+            Some("Origin not allowed")
+        } else {
+            None
+        };
+
+        if let Some(reason) = rejection {
+            debug!("Rejected request: {} ({:?})", reason, request.headers().get("Origin"));
+            let mut error = ErrorResponse::new(Some(reason.into()));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            return Err(error);
+        }
+
+        self.callback.on_request(request, response)
+    }
+}
+
 /// Server handshake role.
 #[allow(missing_copy_implementations)]
 #[derive(Debug)]
@@ -202,30 +567,45 @@ pub struct ServerHandshake<S, C> {
     config: Option<WebSocketConfig>,
     /// Error code/flag. If set, an error will be returned after sending response to the client.
     error_response: Option<ErrorResponse>,
+    /// Subprotocol sent to the client in the response.
+    subprotocol: Option<String>,
+    /// Extensions filled by the callback, attached to the WebSocket.
+    extensions: Extensions,
+    /// Whether to answer `408 Request Timeout` if the handshake times out while reading.
+    send_timeout_response: bool,
     /// Internal stream type.
     _marker: PhantomData<S>,
 }
 
-impl<S: Read + Write, C: Callback> ServerHandshake<S, C> {
+impl<S: Read + Write, C: StreamCallback<S>> ServerHandshake<S, C> {
     /// Start server handshake. `callback` specifies a custom callback which the user can pass to
     /// the handshake, this callback will be called when the a websocket client connects to the
     /// server, you can specify the callback if you want to add additional header to the client
     /// upon join based on the incoming headers.
+    ///
+    /// The size of the request and the time allowed for the handshake are limited by
+    /// [`WebSocketConfig::handshake`].
     pub fn start(stream: S, callback: C, config: Option<WebSocketConfig>) -> MidHandshake<Self> {
         trace!("Server handshake initiated.");
+        let handshake_config = config.unwrap_or_default().handshake;
         MidHandshake {
-            machine: HandshakeMachine::start_read(stream),
+            machine: HandshakeMachine::start_read_with_config(stream, handshake_config),
             role: ServerHandshake {
                 callback: Some(callback),
                 config,
                 error_response: None,
+                subprotocol: None,
+                extensions: Extensions::new(),
+                send_timeout_response: handshake_config.send_timeout_response,
                 _marker: PhantomData,
             },
+            deadline: handshake_config.timeout.map(|timeout| Instant::now() + timeout),
+            set_timeout: None,
         }
     }
 }
 
-impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
+impl<S: Read + Write, C: StreamCallback<S>> HandshakeRole for ServerHandshake<S, C> {
     type IncomingData = Request;
     type InternalStream = S;
     type FinalResult = WebSocket<'static,S>;
@@ -236,19 +616,21 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
     ) -> Result<ProcessingResult<Self::InternalStream, Self::FinalResult>> {
         Ok(match finish {
             StageResult::DoneReading { stream, result, tail } => {
-                if !tail.is_empty() {
+                if !tail.0.is_empty() {
                     return Err(Error::Protocol(ProtocolError::JunkAfterRequest));
                 }
 
                 let response = create_response(&result)?;
                 let callback_result = if let Some(callback) = self.callback.take() {
-                    callback.on_request(&result, response)
+                    callback.on_request(&stream, &result, response, &mut self.extensions)
                 } else {
                     Ok(response)
                 };
 
                 match callback_result {
                     Ok(response) => {
+                        self.subprotocol =
+                            subprotocols(response.headers())?.first().map(|p| p.to_string());
                         let mut output = vec![];
                         write_response(&mut output, &response)?;
                         ProcessingResult::Continue(HandshakeMachine::start_write(stream, output))
@@ -283,22 +665,43 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
                     return Err(Error::Http(http::Response::from_parts(parts, body)));
                 } else {
                     debug!("Server handshake done.");
-                    let websocket = WebSocket::from_raw_socket(stream, Role::Server, self.config);
+                    let mut websocket =
+                        WebSocket::from_raw_socket(stream, Role::Server, self.config);
+                    websocket.set_subprotocol(self.subprotocol.take());
+                    *websocket.extensions_mut() = std::mem::take(&mut self.extensions);
                     ProcessingResult::Done(websocket)
                 }
             }
         })
     }
+
+    fn timeout_response(&self) -> Option<&'static [u8]> {
+        // The callback is taken once the request has been read.
+        if self.send_timeout_response && self.callback.is_some() {
+            Some(b"HTTP/1.1 408 Request Timeout\r\nConnection: close\r\nContent-Length: 0\r\n\r\n")
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{super::machine::TryParse, create_response, Request};
+    use super::{
+        super::{headers::MAX_HEADERS, machine::TryParse},
+        accept_extended_connect, create_extended_connect_response, create_response, Callback,
+        NoCallback, OriginCallback, OriginPolicy, Request, Response, SubprotocolCallback,
+        SubprotocolSelector,
+    };
+    use crate::{
+        error::{Error, ProtocolError},
+        Message,
+    };
 
     #[test]
     fn request_parsing() {
         const DATA: &[u8] = b"GET /script.ws HTTP/1.1\r\nHost: foo.com\r\n\r\n";
-        let (_, req) = Request::try_parse(DATA).unwrap().unwrap();
+        let (_, req) = Request::try_parse(DATA, MAX_HEADERS).unwrap().unwrap();
         assert_eq!(req.uri().path(), "/script.ws");
         assert_eq!(req.headers().get("Host").unwrap(), &b"foo.com"[..]);
     }
@@ -313,7 +716,7 @@ mod tests {
             Sec-WebSocket-Version: 13\r\n\
             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
             \r\n";
-        let (_, req) = Request::try_parse(DATA).unwrap().unwrap();
+        let (_, req) = Request::try_parse(DATA, MAX_HEADERS).unwrap().unwrap();
         let response = create_response(&req).unwrap();
 
         assert_eq!(
@@ -321,4 +724,145 @@ mod tests {
             b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".as_ref()
         );
     }
+
+    #[test]
+    fn subprotocol_selection() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Protocol: chat, superchat\r\n\
+            \r\n";
+        let (_, req) = Request::try_parse(DATA, MAX_HEADERS).unwrap().unwrap();
+        fn select(req: &Request, selector: impl SubprotocolSelector) -> Option<String> {
+            let callback = SubprotocolCallback::with_selector(selector);
+            let response = callback.on_request(req, create_response(req).unwrap()).unwrap();
+            response.headers().get("Sec-WebSocket-Protocol").map(|h| h.to_str().unwrap().to_owned())
+        }
+
+        assert_eq!(select(&req, ["mqtt", "superchat", "chat"]), Some("superchat".into()));
+        assert_eq!(select(&req, ["mqtt"]), None);
+        assert_eq!(select(&req, vec!["chat".to_owned()]), Some("chat".into()));
+        assert_eq!(select(&req, |_: &Request, _: &[&str]| Some("mqtt".to_owned())), None);
+    }
+
+    #[test]
+    fn extended_connect_validation() {
+        fn request(method: http::Method) -> Request {
+            Request::builder()
+                .method(method)
+                .version(http::Version::HTTP_2)
+                .uri("https://localhost/chat")
+                .header("Sec-WebSocket-Version", "13")
+                .body(())
+                .unwrap()
+        }
+        fn reject(request: &Request, protocol: Option<&str>) -> ProtocolError {
+            match create_extended_connect_response(request, protocol) {
+                Err(Error::Protocol(e)) => e,
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+
+        let response =
+            create_extended_connect_response(&request(http::Method::CONNECT), Some("websocket"))
+                .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert!(response.headers().get("Sec-WebSocket-Accept").is_none());
+
+        assert_eq!(
+            reject(&request(http::Method::GET), Some("websocket")),
+            ProtocolError::ExtendedConnectRequired
+        );
+        let mut http11 = request(http::Method::CONNECT);
+        *http11.version_mut() = http::Version::HTTP_11;
+        assert_eq!(reject(&http11, Some("websocket")), ProtocolError::ExtendedConnectRequired);
+        assert_eq!(
+            reject(&request(http::Method::CONNECT), None),
+            ProtocolError::MissingProtocolPseudoHeader
+        );
+        assert_eq!(
+            reject(&request(http::Method::CONNECT), Some("connect-udp")),
+            ProtocolError::MissingProtocolPseudoHeader
+        );
+        let mut unversioned = request(http::Method::CONNECT);
+        unversioned.headers_mut().remove("Sec-WebSocket-Version");
+        assert_eq!(
+            reject(&unversioned, Some("websocket")),
+            ProtocolError::MissingSecWebSocketVersionHeader
+        );
+
+        let stream = std::io::Cursor::new(Vec::new());
+        let mut socket = accept_extended_connect(stream, &response, None).unwrap();
+        assert_eq!(socket.subprotocol(), None);
+        socket.send(Message::Text("Hello over HTTP/2".into())).unwrap();
+        // Server frames are not masked.
+        assert_eq!(socket.get_ref().get_ref().as_slice(), b"\x81\x11Hello over HTTP/2");
+    }
+
+    #[test]
+    fn origin_policies() {
+        let exact = OriginPolicy::Exact(vec!["https://example.com".into()]);
+        assert!(exact.allows("https://example.com"));
+        assert!(exact.allows("HTTPS://Example.com"));
+        assert!(!exact.allows("http://example.com"));
+        assert!(!exact.allows("https://example.com.evil"));
+
+        let wildcard = OriginPolicy::wildcard_subdomain("https://*.example.com").unwrap();
+        assert!(wildcard.allows("https://app.example.com"));
+        assert!(wildcard.allows("https://a.b.example.com"));
+        assert!(!wildcard.allows("https://example.com"));
+        assert!(!wildcard.allows("https://evilexample.com"));
+        assert!(!wildcard.allows("http://app.example.com"));
+        assert!(!wildcard.allows("https://app.example.com:8443"));
+        assert!(OriginPolicy::wildcard_subdomain("https://example.com").is_none());
+
+        let predicate = OriginPolicy::Predicate(std::sync::Arc::new(|o| o.ends_with(":3000")));
+        assert!(predicate.allows("http://localhost:3000"));
+        assert!(!predicate.allows("null"));
+    }
+
+    #[test]
+    fn origin_callback() {
+        fn request(host: &str, origin: Option<&str>) -> Request {
+            let mut builder = Request::builder().uri("/socket").header("Host", host);
+            if let Some(origin) = origin {
+                builder = builder.header("Origin", origin);
+            }
+            builder.body(()).unwrap()
+        }
+        fn check(callback: &OriginCallback<NoCallback>, request: &Request) -> Option<String> {
+            let response = Response::new(());
+            match callback.clone().on_request(request, response) {
+                Ok(_) => None,
+                Err(error) => {
+                    assert_eq!(error.status(), http::StatusCode::FORBIDDEN);
+                    error.into_body()
+                }
+            }
+        }
+
+        let callback =
+            OriginCallback::with_policy(OriginPolicy::Exact(vec!["https://example.com".into()]))
+                .allow_hosts(vec!["example.com", "[::1]:8080"]);
+        assert_eq!(check(&callback, &request("example.com", Some("https://example.com"))), None);
+        assert_eq!(check(&callback, &request("example.com:443", None)), None);
+        assert_eq!(check(&callback, &request("[::1]:8080", None)), None);
+        assert_eq!(check(&callback, &request("[::1]:8081", None)), Some("Host not allowed".into()));
+        assert_eq!(check(&callback, &request("evil.com", None)), Some("Host not allowed".into()));
+        assert_eq!(
+            check(&callback, &request("example.com", Some("https://evil.com"))),
+            Some("Origin not allowed".into())
+        );
+
+        let callback = callback.require_origin(true);
+        assert_eq!(
+            check(&callback, &request("example.com", None)),
+            Some("Origin not allowed".into())
+        );
+    }
 }
@@ -0,0 +1,214 @@
+//! The I/O interface of the protocol.
+//!
+//! With the `std` feature this is `std::io`. Without it, a minimal subset in the style of
+//! `embedded-io`: blocking [`Read`] and [`Write`] traits with an [`Error`] that only carries
+//! its [`ErrorKind`], which is all the protocol inspects.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, IoSliceMut, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::core_io::{Error, ErrorKind, IoSliceMut, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+mod core_io {
+    #[cfg(feature = "alloc")]
+    use alloc::vec::Vec;
+    use core::{
+        fmt,
+        ops::{Deref, DerefMut},
+        result,
+    };
+
+    /// The kinds of errors the protocol distinguishes, named after their `std::io` equivalents.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// The operation needs to block to complete.
+        WouldBlock,
+        /// The operation was interrupted and can be retried.
+        Interrupted,
+        /// The connection was reset by the peer.
+        ConnectionReset,
+        /// A parameter was incorrect.
+        InvalidInput,
+        /// Data not valid for the operation was encountered.
+        InvalidData,
+        /// The stream ended before the operation completed.
+        UnexpectedEof,
+        /// A write returned `Ok(0)`.
+        WriteZero,
+        /// Any other error.
+        Other,
+    }
+
+    impl ErrorKind {
+        fn as_str(self) -> &'static str {
+            match self {
+                ErrorKind::WouldBlock => "operation would block",
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::ConnectionReset => "connection reset",
+                ErrorKind::InvalidInput => "invalid input parameter",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::Other => "other error",
+            }
+        }
+    }
+
+    /// An I/O error: a kind and a static description.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Error {
+        kind: ErrorKind,
+        message: &'static str,
+    }
+
+    impl Error {
+        /// Create an error of the given kind.
+        pub const fn new(kind: ErrorKind, message: &'static str) -> Self {
+            Error { kind, message }
+        }
+
+        /// The kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error::new(kind, kind.as_str())
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    /// Result of an I/O operation.
+    pub type Result<T> = result::Result<T, Error>;
+
+    /// A buffer to read into, the counterpart of `std::io::IoSliceMut`.
+    #[derive(Debug)]
+    pub struct IoSliceMut<'a>(&'a mut [u8]);
+
+    impl<'a> IoSliceMut<'a> {
+        /// Wrap a buffer.
+        pub fn new(buf: &'a mut [u8]) -> Self {
+            IoSliceMut(buf)
+        }
+    }
+
+    impl Deref for IoSliceMut<'_> {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            self.0
+        }
+    }
+
+    impl DerefMut for IoSliceMut<'_> {
+        fn deref_mut(&mut self) -> &mut [u8] {
+            self.0
+        }
+    }
+
+    /// A source of bytes, e.g. a socket of an embedded network stack.
+    pub trait Read {
+        /// Read into `buf`, returning the number of bytes read. `Ok(0)` means end of stream.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Read into several buffers in order. By default only the first non-empty one is used.
+        fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+            match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+                Some(buf) => self.read(buf),
+                None => Ok(0),
+            }
+        }
+    }
+
+    /// A sink of bytes, e.g. a socket of an embedded network stack.
+    pub trait Write {
+        /// Write from `buf`, returning the number of bytes written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Push buffered data out to the destination.
+        fn flush(&mut self) -> Result<()>;
+
+        /// Write all of `buf`, retrying on [`ErrorKind::Interrupted`].
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(n) => buf = &buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+
+        fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+            (**self).read_vectored(bufs)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = buf.len().min(self.len());
+            let (head, tail) = self.split_at(len);
+            buf[..len].copy_from_slice(head);
+            *self = tail;
+            Ok(len)
+        }
+    }
+
+    impl Write for &mut [u8] {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = buf.len().min(self.len());
+            let (head, tail) = core::mem::take(self).split_at_mut(len);
+            head.copy_from_slice(&buf[..len]);
+            *self = tail;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
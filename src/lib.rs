@@ -1,4 +1,11 @@
 //! Lightweight, flexible WebSockets for Rust.
+//!
+//! Without the `std` feature the crate is `no_std`. The frame layer and
+//! [`WebSocketContext`](protocol::WebSocketContext) then run over the traits in [`io`]. Without
+//! the `alloc` feature either, a context is created with
+//! [`WebSocketContext::from_static_buffers`](protocol::WebSocketContext::from_static_buffers) and
+//! keeps control frames in a [`Payload`](protocol::frame::Payload) of at most 125 bytes.
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(
     missing_copy_implementations,
     missing_debug_implementations,
@@ -13,25 +20,32 @@
 
 #![warn(unused_unsafe)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[cfg(feature = "handshake")]
 pub use http;
+#[cfg(feature = "std")]
 pub mod buffer;
 #[cfg(feature = "handshake")]
 pub mod client;
 pub mod error;
 #[cfg(feature = "handshake")]
 pub mod handshake;
+pub mod io;
 pub mod protocol;
 #[cfg(feature = "handshake")]
+mod proxy;
+#[cfg(feature = "handshake")]
+mod reconnect;
+#[cfg(feature = "handshake")]
 mod server;
+#[cfg(feature = "std")]
 pub mod stream;
 #[cfg(all(any(feature = "native-tls", feature = "__rustls-tls"), feature = "handshake"))]
 mod tls;
 pub mod util;
 
-const READ_BUFFER_CHUNK_SIZE: usize = 4096;
-type ReadBuffer = buffer::ReadBuffer<READ_BUFFER_CHUNK_SIZE>;
-
 pub use crate::{
     error::{Error, Result},
     protocol::{Message, WebSocket},
@@ -39,12 +53,25 @@ pub use crate::{
 
 #[cfg(feature = "handshake")]
 pub use crate::{
-    client::{client, connect},
+    client::{client, connect, ClientRequestBuilder},
     handshake::{client::ClientHandshake, server::ServerHandshake, HandshakeError},
+    proxy::{ProxyConfig, ProxyScheme},
+    reconnect::{
+        ClientSocket, DefaultReconnectPolicy, Disconnect, ReconnectConfig, ReconnectPolicy,
+        ReconnectingClient,
+    },
     server::{accept, accept_hdr, accept_hdr_with_config, accept_with_config},
 };
 
+#[cfg(all(unix, feature = "handshake"))]
+pub use crate::{
+    client::{connect_unix, connect_unix_socket, connect_unix_with_config},
+    server::{accept_unix, accept_unix_hdr_with_config},
+};
+
 #[cfg(all(any(feature = "native-tls", feature = "__rustls-tls"), feature = "handshake"))]
-pub use tls::{client_tls, client_tls_with_config, Connector};
+pub use tls::{
+    accept_tls, accept_tls_with_config, client_tls, client_tls_with_config, Acceptor, Connector,
+};
 ///
 pub mod storage;
\ No newline at end of file
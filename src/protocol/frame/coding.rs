@@ -1,12 +1,13 @@
 //! Various codes defined in RFC 6455.
 
-use std::{
+use core::{
     convert::{From, Into},
     fmt,
 };
 
 /// WebSocket message opcode as in RFC 6455.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum OpCode {
     /// Data (text or binary).
     Data(Data),
@@ -16,6 +17,7 @@ pub enum OpCode {
 
 /// Data opcodes as in RFC 6455
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Data {
     /// 0x0 denotes a continuation frame
     Continue,
@@ -29,6 +31,7 @@ pub enum Data {
 
 /// Control opcodes as in RFC 6455
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Control {
     /// 0x8 denotes a connection close
     Close,
@@ -116,6 +119,7 @@ impl From<u8> for OpCode {
 use self::CloseCode::*;
 /// Status code used to indicate why an endpoint is closing the WebSocket connection.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CloseCode {
     /// Indicates a normal closure, meaning that the purpose for
     /// which the connection was established has been fulfilled.
@@ -1,26 +1,128 @@
-use byteorder::{ByteOrder, NetworkEndian,WriteBytesExt};
-use std::{
+#[cfg(feature = "alloc")]
+use alloc::{
     borrow::Cow,
-    default::Default,
-    fmt,
-    result::Result as StdResult,
-    str::Utf8Error,
-    string::{FromUtf8Error, String}, io::{Write, ErrorKind},
+    string::{FromUtf8Error, String},
+    vec::Vec,
+};
+use byteorder::{ByteOrder, NetworkEndian};
+#[cfg(not(feature = "alloc"))]
+use core::ops::Deref;
+#[cfg(feature = "alloc")]
+use core::result::Result as StdResult;
+use core::{default::Default, fmt, str::Utf8Error};
+
+use crate::{
+    io::Write,
+    storage::managed_vec::ManagedVec,
 };
 
 use super::{
     coding::{CloseCode, Control, OpCode},
-    mask::{apply_mask, generate_mask},
+    mask::{apply_mask, apply_mask_copy, MaskGenerator},
 };
 use crate::{error::{Error, ProtocolError, Result}, storage::U8RingBuffer};
 
+/// The longest payload of a control frame, i.e. of a ping, pong or close frame.
+pub const MAX_CONTROL_PAYLOAD_LEN: usize = 125;
+
+/// The payload of a [`WriteFrame`] and of ping and pong messages.
+#[cfg(feature = "alloc")]
+pub type Payload = Vec<u8>;
+
+/// The payload of a [`WriteFrame`] and of ping and pong messages. Without `alloc` it holds
+/// a control frame, data messages are written with
+/// [`fast_write_text_slice`](crate::protocol::WebSocketContext::fast_write_text_slice).
+#[cfg(not(feature = "alloc"))]
+pub type Payload = heapless::Vec<u8, MAX_CONTROL_PAYLOAD_LEN>;
+
+/// The reason of a [`CloseFrame`].
+#[cfg(feature = "alloc")]
+pub type CloseReason<'t> = Cow<'t, str>;
+
+/// The reason of a [`CloseFrame`], like a `Cow<str>` that owns a copy of at most the 123
+/// bytes a close frame has room for.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg(not(feature = "alloc"))]
+pub enum CloseReason<'t> {
+    /// A borrowed reason.
+    Borrowed(&'t str),
+    /// A copied reason.
+    Owned(heapless::String<MAX_CLOSE_REASON_LEN>),
+}
+
+/// The longest reason that fits into a close frame next to its code.
+#[cfg(not(feature = "alloc"))]
+const MAX_CLOSE_REASON_LEN: usize = MAX_CONTROL_PAYLOAD_LEN - 2;
+
+#[cfg(not(feature = "alloc"))]
+impl<'t> CloseReason<'t> {
+    /// Copy the reason, cut at a character boundary if it is longer than 123 bytes.
+    pub fn into_owned(self) -> heapless::String<MAX_CLOSE_REASON_LEN> {
+        match self {
+            CloseReason::Borrowed(reason) => {
+                let mut end = reason.len().min(MAX_CLOSE_REASON_LEN);
+                while !reason.is_char_boundary(end) {
+                    end -= 1;
+                }
+                reason[..end].into()
+            }
+            CloseReason::Owned(reason) => reason,
+        }
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl Deref for CloseReason<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            CloseReason::Borrowed(reason) => reason,
+            CloseReason::Owned(reason) => reason,
+        }
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl<'t> From<&'t str> for CloseReason<'t> {
+    fn from(reason: &'t str) -> Self {
+        CloseReason::Borrowed(reason)
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl From<heapless::String<MAX_CLOSE_REASON_LEN>> for CloseReason<'_> {
+    fn from(reason: heapless::String<MAX_CLOSE_REASON_LEN>) -> Self {
+        CloseReason::Owned(reason)
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl PartialEq for CloseReason<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl Eq for CloseReason<'_> {}
+
+#[cfg(not(feature = "alloc"))]
+impl fmt::Display for CloseReason<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
 /// A struct representing the close command.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CloseFrame<'t> {
     /// The reason as a code.
     pub code: CloseCode,
     /// The reason as text string.
-    pub reason: Cow<'t, str>,
+    pub reason: CloseReason<'t>,
 }
 
 impl<'t> CloseFrame<'t> {
@@ -28,6 +130,31 @@ impl<'t> CloseFrame<'t> {
     pub fn into_owned(self) -> CloseFrame<'static> {
         CloseFrame { code: self.code, reason: self.reason.into_owned().into() }
     }
+
+    /// Parse the payload of a close frame.
+    fn parse(payload: &[u8]) -> Result<Option<CloseFrame<'static>>> {
+        match payload.len() {
+            0 => Ok(None),
+            1 => Err(Error::Protocol(ProtocolError::InvalidCloseSequence)),
+            _ => {
+                let code = NetworkEndian::read_u16(&payload[0..2]).into();
+                let reason = core::str::from_utf8(&payload[2..])?;
+                Ok(Some(CloseFrame { code, reason: reason.into() }.into_owned()))
+            }
+        }
+    }
+
+    /// Encode the close frame as the payload of a control frame. Without `alloc` a reason
+    /// longer than 123 bytes is cut off.
+    fn to_payload(&self) -> Payload {
+        #[cfg(not(feature = "alloc"))]
+        let reason = CloseReason::Borrowed(&self.reason).into_owned();
+        #[cfg(feature = "alloc")]
+        let reason = &self.reason;
+
+        let code = u16::from(self.code).to_be_bytes();
+        code.iter().chain(reason.as_bytes()).copied().collect()
+    }
 }
 
 impl<'t> fmt::Display for CloseFrame<'t> {
@@ -39,6 +166,7 @@ impl<'t> fmt::Display for CloseFrame<'t> {
 /// A struct representing a WebSocket frame header.
 #[allow(missing_copy_implementations)]
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FrameHeader {
     /// Indicates that the frame is the last one of a possibly fragmented message.
     pub is_final: bool,
@@ -99,8 +227,8 @@ impl FrameHeader {
         output.write_all(&[one, two])?;
         match lenfmt {
             LengthFormat::U8(_) => (),
-            LengthFormat::U16 => output.write_u16::<NetworkEndian>(length as u16)?,
-            LengthFormat::U64 => output.write_u64::<NetworkEndian>(length)?,
+            LengthFormat::U16 => output.write_all(&(length as u16).to_be_bytes())?,
+            LengthFormat::U64 => output.write_all(&length.to_be_bytes())?,
         }
 
         if let Some(ref mask) = self.mask {
@@ -110,11 +238,11 @@ impl FrameHeader {
         Ok(())
     }
 
-    /// Generate a random frame mask and store this in the header.
+    /// Generate a random frame mask with `generator` and store this in the header.
     ///
     /// Of course this does not change frame contents. It just generates a mask.
-    pub(crate) fn set_random_mask(&mut self) {
-        self.mask = Some(generate_mask())
+    pub(crate) fn set_random_mask(&mut self, generator: &dyn MaskGenerator) {
+        self.mask = Some(generator.generate_mask())
     }
 }
 
@@ -123,15 +251,13 @@ impl FrameHeader {
     /// Returns `None` if insufficient data.
     /// Payload size is returned along with the header.
     fn parse_internal(cursor: &mut U8RingBuffer) -> Result<Option<(Self, u64)>> {
-        let position = cursor.0.position();
-        if cursor.0.len()<2{
+        // Copy the header out, so nothing is allocated if it wraps around the buffer.
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let available = cursor.0.read_allocated(0, &mut header);
+        if available < 2 {
             return Ok(None);
         }
-        let (first, second) = {
-            let head = cursor.0.dequeue_many_contiguous(2);
-            //trace!("Parsed headers {:?}", head);
-            (head[0], head[1])
-        };
+        let (first, second) = (header[0], header[1]);
 
         //trace!("First: {:b}", first);
         //trace!("Second: {:b}", second);
@@ -148,39 +274,29 @@ impl FrameHeader {
         let masked = second & 0x80 != 0;
         //trace!("Masked: {:?}", masked);
 
-        let length = {
-            let length_byte = second & 0x7F;
-            let length_length = LengthFormat::for_byte(length_byte).extra_bytes();
-            if length_length > 0 {
-                match cursor.read_uint::<NetworkEndian>(length_length) {
-                    Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => {
-                        return Ok(None);
-                    }
-                    Err(err) => {
-                        return Err(err.into());
-                    }
-                    Ok(read) => read,
-                }
-            } else {
-                length_byte as u64
-            }
+        let length_byte = second & 0x7F;
+        let length_length = LengthFormat::for_byte(length_byte).extra_bytes();
+        let header_length = 2 + length_length + if masked { 4 } else { 0 };
+
+        // Check for the whole header first, so nothing is consumed if part of it is missing.
+        if available < header_length {
+            return Ok(None);
+        }
+
+        let length = if length_length > 0 {
+            NetworkEndian::read_uint(&header[2..2 + length_length], length_length)
+        } else {
+            length_byte as u64
         };
 
         let mask = if masked {
-            if cursor.0.len() <4{
-                cursor.0.rollback(position, 2);
-                return Ok(None);
-            }
-
             let mut mask_bytes = [0u8; 4];
-            let bytes = cursor.0.dequeue_many_contiguous(4);
-            for i in 0..4{
-                mask_bytes[i] = bytes[i];
-            }
+            mask_bytes.copy_from_slice(&header[2 + length_length..header_length]);
             Some(mask_bytes)
         } else {
             None
         };
+        cursor.0.consume(header_length);
 
         // Disallow bad opcode
         // match opcode {
@@ -197,35 +313,46 @@ impl FrameHeader {
 }
 
 /// A struct representing a WebSocket frame.
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct FastWriteFrame {
+///
+/// The payload is not stored in the frame, it is borrowed only for the duration of `format()`.
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "alloc", derive(Clone))]
+pub struct FastWriteFrame<'a> {
     header: FrameHeader,
-    payload: *mut [u8],
+    /// Buffer the masked payload is copied into, allocated on the first masked frame unless
+    /// it is borrowed.
+    scratch: ManagedVec<'a, u8>,
 }
 
-impl FastWriteFrame {
+/// Size of the chunks a masked payload is copied and written in.
+const MASK_CHUNK_SIZE: usize = 4096;
+
+/// The longest possible frame header: 2 bytes, 8 bytes of length and 4 bytes of mask.
+const MAX_HEADER_LEN: usize = 14;
+
+/// Size of a borrowed mask buffer, which copies and writes masked payloads in chunks of 256.
+pub(crate) const STATIC_SCRATCH_LEN: usize = MAX_HEADER_LEN + 256;
+
+impl<'a> FastWriteFrame<'a> {
     /// new
+    #[cfg(feature = "alloc")]
     pub fn new() -> Self{
         FastWriteFrame{
             header: Default::default(),
-            payload: unsafe { &mut NULLPTR }
+            scratch: ManagedVec::new(),
         }
     }
 
-    /// Get the length of the frame.
-    /// This is the length of the header + the length of the payload.
-    #[inline]
-    pub fn len(&self) -> usize {
-        unsafe{
-            let length = (*self.payload).len();
-            self.header.len(length as u64) + length
-        }
+    /// Create a frame that masks payloads in `scratch` instead of allocating a buffer.
+    pub(crate) fn with_scratch(scratch: &'a mut [u8; STATIC_SCRATCH_LEN]) -> Self {
+        FastWriteFrame { header: Default::default(), scratch: ManagedVec::from(&mut scratch[..]) }
     }
 
-    /// Check if the frame is empty.
+    /// Get the length of the frame with a payload of `payload_len` bytes.
+    /// This is the length of the header + the length of the payload.
     #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    pub fn len(&self, payload_len: usize) -> usize {
+        self.header.len(payload_len as u64) + payload_len
     }
 
     /// Get a reference to the frame's header.
@@ -240,12 +367,6 @@ impl FastWriteFrame {
         &mut self.header
     }
 
-    /// Get a reference to the frame's payload.
-    #[inline]
-    pub fn payload(&self) -> &mut [u8] {
-        unsafe { &mut *self.payload }
-    }
-
     /// Test whether the frame is masked.
     #[inline]
     pub(crate) fn is_masked(&self) -> bool {
@@ -255,84 +376,82 @@ impl FastWriteFrame {
     /// Generate a random mask for the frame.
     ///
     /// This just generates a mask, payload is not changed. The actual masking is performed
-    /// either on `format()` or on `apply_mask()` call.
+    /// while copying the payload on `format()`.
     #[inline]
-    pub(crate) fn set_random_mask(&mut self) {
-        self.header.set_random_mask()
-    }
-
-    /// This method unmasks the payload and should only be called on frames that are actually
-    /// masked. In other words, those frames that have just been received from a client endpoint.
-    #[inline]
-    pub(crate) fn apply_mask(&mut self) {
-        if let Some(mask) = self.header.mask.take() {
-            apply_mask(unsafe { &mut *self.payload }, mask)
-        }
+    pub(crate) fn set_random_mask(&mut self, generator: &dyn MaskGenerator) {
+        self.header.set_random_mask(generator)
     }
 
     /// reuse a new data frame.
+    ///
+    /// The payload is passed to the following `format()` call.
     #[inline]
-    pub fn reuse(&mut self,data: &mut [u8], opcode: OpCode, is_final: bool){
+    pub fn reuse(&mut self, opcode: OpCode, is_final: bool){
         //debug_assert!(matches!(opcode, OpCode::Data(_)), "Invalid opcode for data frame.");
-        self.payload = data;
         self.header.is_final = is_final;
         self.header.opcode = opcode;
     }
 
     /// Create a new data frame.
     #[inline]
-    pub fn message(data: Vec<u8>, opcode: OpCode, is_final: bool) -> WriteFrame {
-        debug_assert!(matches!(opcode, OpCode::Data(_)), "Invalid opcode for data frame.");
-
-        WriteFrame { header: FrameHeader { is_final, opcode, ..FrameHeader::default() }, payload: data }
+    pub fn message(data: Payload, opcode: OpCode, is_final: bool) -> WriteFrame {
+        WriteFrame::message(data, opcode, is_final)
     }
 
     /// Create a new Pong control frame.
     #[inline]
-    pub fn pong(data: Vec<u8>) -> WriteFrame{
-        WriteFrame {
-            header: FrameHeader {
-                opcode: OpCode::Control(Control::Pong),
-                ..FrameHeader::default()
-            },
-            payload: data,
-        }
+    pub fn pong(data: Payload) -> WriteFrame {
+        WriteFrame::pong(data)
     }
 
     /// Create a new Ping control frame.
     #[inline]
-    pub fn ping(data: Vec<u8>) -> WriteFrame {
-        WriteFrame {
-            header: FrameHeader {
-                opcode: OpCode::Control(Control::Ping),
-                ..FrameHeader::default()
-            },
-            payload: data,
-        }
+    pub fn ping(data: Payload) -> WriteFrame {
+        WriteFrame::ping(data)
     }
 
     /// Create a new Close control frame.
     #[inline]
     pub fn close(msg: Option<CloseFrame>) -> WriteFrame {
-        let payload = if let Some(CloseFrame { code, reason }) = msg {
-            let mut p = Vec::with_capacity(reason.as_bytes().len() + 2);
-            p.write_u16::<NetworkEndian>(code.into()).unwrap(); // can't fail
-            p.extend_from_slice(reason.as_bytes());
-            p
-        } else {
-            Vec::new()
-        };
-
-        WriteFrame { header: FrameHeader::default(), payload }
+        WriteFrame::close(msg)
     }
 
-     /// Write a frame out to a buffer
-    pub fn format(&mut self, output: &mut impl Write) -> Result<()> {
-        unsafe{
-            self.header.format((*self.payload).len() as u64, output)?;
+    /// Write a frame with the given payload out to a buffer.
+    ///
+    /// A masked payload is masked while being copied into an internal buffer in chunks of
+    /// `MASK_CHUNK_SIZE` (or less if the buffer is borrowed), which are written out one after
+    /// another. The payload itself is read only once and left untouched.
+    pub fn format(&mut self, payload: &[u8], output: &mut impl Write) -> Result<()> {
+        let mask = match self.header.mask {
+            Some(mask) => mask,
+            None => {
+                self.header.format(payload.len() as u64, output)?;
+                output.write_all(payload)?;
+                return Ok(());
+            }
+        };
+
+        if self.scratch.is_empty() {
+            let len = self.scratch.max_len().min(MAX_HEADER_LEN + MASK_CHUNK_SIZE);
+            self.scratch.resize(len, 0).expect("Bug: scratch buffer longer than its storage");
+        }
+        let scratch_len = self.scratch.len();
+        let chunk_size = (scratch_len - MAX_HEADER_LEN) & !3;
+        let mut header = &mut self.scratch[..];
+        self.header.format(payload.len() as u64, &mut header)?;
+        let mut filled = scratch_len - header.len();
+        self.header.mask = None;
+
+        // The chunk size is a multiple of 4, so every chunk starts at the same mask offset.
+        for chunk in payload.chunks(chunk_size) {
+            apply_mask_copy(&mut self.scratch[filled..filled + chunk.len()], chunk, mask);
+            output.write_all(&self.scratch[..filled + chunk.len()])?;
+            filled = 0;
+        }
+        if filled > 0 {
+            // An empty payload, only the header was written into the buffer.
+            output.write_all(&self.scratch[..filled])?;
         }
-        self.apply_mask();
-        output.write_all(self.payload())?;
         Ok(())
     }
 }
@@ -341,7 +460,7 @@ impl FastWriteFrame {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct WriteFrame {
     header: FrameHeader,
-    payload: Vec<u8>,
+    payload: Payload,
 }
 
 impl WriteFrame {
@@ -349,7 +468,10 @@ impl WriteFrame {
     pub fn new() -> Self{
         WriteFrame{
             header: Default::default(),
+            #[cfg(feature = "alloc")]
             payload: Vec::with_capacity(512),
+            #[cfg(not(feature = "alloc"))]
+            payload: Payload::new(),
         }
     }
 
@@ -381,7 +503,7 @@ impl WriteFrame {
 
     /// Get a reference to the frame's payload.
     #[inline]
-    pub fn payload(&self) -> &Vec<u8> {
+    pub fn payload(&self) -> &Payload {
         &self.payload
     }
 
@@ -393,7 +515,7 @@ impl WriteFrame {
 
     /// Get a mutable reference to the frame's payload.
     #[inline]
-    pub fn payload_mut(&mut self) -> &mut Vec<u8> {
+    pub fn payload_mut(&mut self) -> &mut Payload {
         &mut self.payload
     }
 
@@ -408,8 +530,8 @@ impl WriteFrame {
     /// This just generates a mask, payload is not changed. The actual masking is performed
     /// either on `format()` or on `apply_mask()` call.
     #[inline]
-    pub(crate) fn set_random_mask(&mut self) {
-        self.header.set_random_mask()
+    pub(crate) fn set_random_mask(&mut self, generator: &dyn MaskGenerator) {
+        self.header.set_random_mask(generator)
     }
 
     /// This method unmasks the payload and should only be called on frames that are actually
@@ -422,12 +544,14 @@ impl WriteFrame {
     }
 
     /// Consume the frame into its payload as binary.
+    #[cfg(feature = "alloc")]
     #[inline]
     pub fn into_data(&self) -> Vec<u8> {
         self.payload.clone()
     }
 
     /// Consume the frame into its payload as string.
+    #[cfg(feature = "alloc")]
     #[inline]
     pub fn into_string(self) -> StdResult<String, FromUtf8Error> {
         String::from_utf8(self.payload)
@@ -436,28 +560,18 @@ impl WriteFrame {
     /// Get frame payload as `&str`.
     #[inline]
     pub fn to_text(&self) -> Result<&str, Utf8Error> {
-        std::str::from_utf8(&self.payload)
+        core::str::from_utf8(&self.payload)
     }
 
     /// Consume the frame into a closing frame.
     #[inline]
     pub(crate) fn into_close(&self) -> Result<Option<CloseFrame<'static>>> {
-        match self.payload.len() {
-            0 => Ok(None),
-            1 => Err(Error::Protocol(ProtocolError::InvalidCloseSequence)),
-            _ => {
-                let mut data = self.payload.clone();
-                let code = NetworkEndian::read_u16(&data[0..2]).into();
-                data.drain(0..2);
-                let text = String::from_utf8(data)?;
-                Ok(Some(CloseFrame { code, reason: text.into() }))
-            }
-        }
+        CloseFrame::parse(&self.payload)
     }
 
     /// reuse a new data frame.
     #[inline]
-    pub fn reuse(&mut self,data: Vec<u8>, opcode: OpCode, is_final: bool){
+    pub fn reuse(&mut self,data: Payload, opcode: OpCode, is_final: bool){
         //debug_assert!(matches!(opcode, OpCode::Data(_)), "Invalid opcode for data frame.");
         //self.payload.clear();
         self.payload = data;
@@ -467,7 +581,7 @@ impl WriteFrame {
 
     /// Create a new data frame.
     #[inline]
-    pub fn message(data: Vec<u8>, opcode: OpCode, is_final: bool) -> WriteFrame {
+    pub fn message(data: Payload, opcode: OpCode, is_final: bool) -> WriteFrame {
         debug_assert!(matches!(opcode, OpCode::Data(_)), "Invalid opcode for data frame.");
 
         WriteFrame { header: FrameHeader { is_final, opcode, ..FrameHeader::default() }, payload: data }
@@ -475,7 +589,7 @@ impl WriteFrame {
 
     /// Create a new Pong control frame.
     #[inline]
-    pub fn pong(data: Vec<u8>) -> WriteFrame{
+    pub fn pong(data: Payload) -> WriteFrame{
         WriteFrame {
             header: FrameHeader {
                 opcode: OpCode::Control(Control::Pong),
@@ -487,7 +601,7 @@ impl WriteFrame {
 
     /// Create a new Ping control frame.
     #[inline]
-    pub fn ping(data: Vec<u8>) -> WriteFrame {
+    pub fn ping(data: Payload) -> WriteFrame {
         WriteFrame {
             header: FrameHeader {
                 opcode: OpCode::Control(Control::Ping),
@@ -500,20 +614,12 @@ impl WriteFrame {
     /// Create a new Close control frame.
     #[inline]
     pub fn close(msg: Option<CloseFrame>) -> WriteFrame {
-        let payload = if let Some(CloseFrame { code, reason }) = msg {
-            let mut p = Vec::with_capacity(reason.as_bytes().len() + 2);
-            p.write_u16::<NetworkEndian>(code.into()).unwrap(); // can't fail
-            p.extend_from_slice(reason.as_bytes());
-            p
-        } else {
-            Vec::new()
-        };
-
+        let payload = msg.map_or_else(Payload::new, |frame| frame.to_payload());
         WriteFrame { header: FrameHeader::default(), payload }
     }
 
     /// Create a frame from given header and data.
-    pub fn from_payload(header: FrameHeader, payload: Vec<u8>) -> Self {
+    pub fn from_payload(header: FrameHeader, payload: Payload) -> Self {
         WriteFrame { header, payload }
     }
 
@@ -534,25 +640,25 @@ impl WriteFrame {
     }
 }
 
-static mut NULLPTR: [u8; 1] = [1;1];
-
 /// A struct representing a WebSocket frame.
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct ReadFrame{
+///
+/// The payload of a frame read from a stream is borrowed from the read buffer.
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "alloc", derive(Clone))]
+pub struct ReadFrame<'a> {
     header: FrameHeader,
-    payload: *mut [u8],
+    payload: ManagedVec<'a, u8>,
 }
 
-impl ReadFrame{
+impl<'a> ReadFrame<'a> {
     /// new
     pub fn new() -> Self{
-        unsafe{
-            ReadFrame{
-                header: Default::default(),
-                payload:&mut NULLPTR
-            }
+        ReadFrame{
+            header: Default::default(),
+            payload: ManagedVec::from(&mut [][..]),
         }
     }
+
     /// set_header
     #[inline]
     pub fn set_header(&mut self,header: FrameHeader){
@@ -563,10 +669,8 @@ impl ReadFrame{
     /// This is the length of the header + the length of the payload.
     #[inline]
     pub fn len(&self) -> usize {
-        unsafe{
-            let length = (*self.payload).len();
-            self.header.len(length as u64) + length
-        }
+        let length = self.payload.len();
+        self.header.len(length as u64) + length
     }
 
     /// Check if the frame is empty.
@@ -582,31 +686,25 @@ impl ReadFrame{
     }
 
     /// Consume the frame into its payload as binary.
+    #[cfg(feature = "alloc")]
     #[inline]
     pub fn into_data(&self) -> Vec<u8> {
-        unsafe{
-            let payload = &mut (*self.payload);
-            payload.to_vec()
-        }
+        self.payload.to_vec()
+    }
+
+    /// Copy the payload of a control frame, which has at most 125 bytes.
+    ///
+    /// # Panics
+    /// Panics without `alloc` if the payload is longer.
+    #[inline]
+    pub(crate) fn control_payload(&self) -> Payload {
+        self.payload.iter().copied().collect()
     }
 
     /// Consume the frame into a closing frame.
     #[inline]
     pub(crate) fn into_close(&self) -> Result<Option<CloseFrame<'static>>> {
-        unsafe{
-            let payload = (&mut (*self.payload)).to_vec();
-            match payload.len() {
-                0 => Ok(None),
-                1 => Err(Error::Protocol(ProtocolError::InvalidCloseSequence)),
-                _ => {
-                    let mut data = payload;
-                    let code = NetworkEndian::read_u16(&data[0..2]).into();
-                    data.drain(0..2);
-                    let text = String::from_utf8(data)?;
-                    Ok(Some(CloseFrame { code, reason: text.into() }))
-                }
-            }
-        }
+        CloseFrame::parse(&self.payload)
     }
 
     /// Get a mutable reference to the frame's header.
@@ -615,18 +713,22 @@ impl ReadFrame{
         &mut self.header
     }
 
-    /// Get a reference to the frame's payload.
+    /// Borrow `value` as the frame's payload.
     #[inline]
-    pub fn set_payload(&mut self,value :&mut [u8]) {
-        self.payload = value;
+    pub fn set_payload(&mut self, value: &'a mut [u8]) {
+        self.payload = ManagedVec::from_filled(value);
     }
 
     /// Get a reference to the frame's payload.
     #[inline]
-    pub fn payload(&self) -> &mut [u8] {
-        unsafe{
-            &mut(*self.payload)
-        }
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Get a mutable reference to the frame's payload.
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.payload
     }
 
     /// Test whether the frame is masked.
@@ -640,26 +742,27 @@ impl ReadFrame{
     /// This just generates a mask, payload is not changed. The actual masking is performed
     /// either on `format()` or on `apply_mask()` call.
     #[inline]
-    pub(crate) fn set_random_mask(&mut self) {
-        self.header.set_random_mask()
+    pub(crate) fn set_random_mask(&mut self, generator: &dyn MaskGenerator) {
+        self.header.set_random_mask(generator)
     }
 
     /// This method unmasks the payload and should only be called on frames that are actually
     /// masked. In other words, those frames that have just been received from a client endpoint.
     #[inline]
     pub(crate) fn apply_mask(&mut self) {
-        // if let Some(mask) = self.header.mask.take() {
-        //     apply_mask(&mut self.payload, mask)
-        // }
+        if let Some(mask) = self.header.mask.take() {
+            apply_mask(&mut self.payload, mask)
+        }
     }
 
     /// Create a frame from given header and data.
-    pub fn from_payload(header: FrameHeader, payload: &mut [u8]) -> Self {
-        ReadFrame { header, payload }
+    #[cfg(feature = "alloc")]
+    pub fn from_payload(header: FrameHeader, payload: Vec<u8>) -> Self {
+        ReadFrame { header, payload: payload.into() }
     }
 }
 
-impl fmt::Display for ReadFrame {
+impl fmt::Display for ReadFrame<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -692,8 +795,7 @@ reserved: {} {} {}
 opcode: {}
 length: {}
 payload length: {}
-payload: 0x{}
-            ",
+payload: 0x",
             self.header.is_final,
             self.header.rsv1,
             self.header.rsv2,
@@ -702,10 +804,32 @@ payload: 0x{}
             // self.mask.map(|mask| format!("{:?}", mask)).unwrap_or("NONE".into()),
             self.len(),
             self.payload.len(),
-            self.payload.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
+        )?;
+        for byte in self.payload.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_str("\n            ")
+    }
+}
+#[cfg(feature = "defmt")]
+impl defmt::Format for WriteFrame {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "<FRAME> final: {=bool} reserved: {=bool} {=bool} {=bool} opcode: {} length: \
+             {=usize} payload length: {=usize} payload: 0x{=[u8]:x}",
+            self.header.is_final,
+            self.header.rsv1,
+            self.header.rsv2,
+            self.header.rsv3,
+            self.header.opcode,
+            self.len(),
+            self.payload.len(),
+            self.payload,
         )
     }
 }
+
 /// Handling of the length format.
 enum LengthFormat {
     U8(u8),
@@ -1,18 +1,120 @@
+#[cfg(target_has_atomic = "64")]
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::cell::Cell;
+
 /// Generate a random frame mask.
+#[cfg(feature = "std")]
 #[inline]
 pub fn generate_mask() -> [u8; 4] {
     rand::random()
 }
 
+/// A source of masks for the frames sent by a client.
+///
+/// RFC 6455 requires masks to be unpredictable, which [`SecureMask`] provides. The other
+/// generators trade that for speed ([`FastMask`]) or reproducible output in tests
+/// ([`FixedMask`], [`SeededMask`]).
+///
+/// Generators are referenced as `&'static dyn MaskGenerator` so that
+/// [`WebSocketConfig`](crate::protocol::WebSocketConfig) stays `Copy`. Put a generator into a
+/// `static` or leak a `Box` to create one at runtime.
+pub trait MaskGenerator: fmt::Debug + Send + Sync {
+    /// Generate the mask for the next frame.
+    fn generate_mask(&self) -> [u8; 4];
+}
+
+/// Masks from the thread-local cryptographically secure RNG of `rand`, the default.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SecureMask;
+
+#[cfg(feature = "std")]
+impl MaskGenerator for SecureMask {
+    #[inline]
+    fn generate_mask(&self) -> [u8; 4] {
+        generate_mask()
+    }
+}
+
+/// Masks from a fast non-cryptographic thread-local xorshift generator.
+///
+/// Each thread seeds its generator from the secure RNG once. The masks are not suitable when
+/// untrusted scripts may choose the payload, as in browsers.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastMask;
+
+#[cfg(feature = "std")]
+impl MaskGenerator for FastMask {
+    #[inline]
+    fn generate_mask(&self) -> [u8; 4] {
+        thread_local! {
+            static STATE: Cell<u64> = Cell::new(rand::random::<u64>() | 1);
+        }
+        STATE.with(|state| {
+            // xorshift64* (Marsaglia, Vigna)
+            let mut x = state.get();
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            state.set(x);
+            ((x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as u32).to_ne_bytes()
+        })
+    }
+}
+
+/// Always the same mask, for byte-exact tests of the client output.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedMask(pub [u8; 4]);
+
+impl MaskGenerator for FixedMask {
+    #[inline]
+    fn generate_mask(&self) -> [u8; 4] {
+        self.0
+    }
+}
+
+/// A deterministic sequence of masks derived from a seed, for reproducible tests.
+///
+/// The sequence is shared by all connections using the generator. Needs 64-bit atomics.
+#[cfg(target_has_atomic = "64")]
+#[derive(Debug)]
+pub struct SeededMask {
+    state: AtomicU64,
+}
+
+#[cfg(target_has_atomic = "64")]
+impl SeededMask {
+    /// Create a generator starting the sequence at `seed`.
+    pub const fn new(seed: u64) -> Self {
+        SeededMask { state: AtomicU64::new(seed) }
+    }
+}
+
+#[cfg(target_has_atomic = "64")]
+impl MaskGenerator for SeededMask {
+    #[inline]
+    fn generate_mask(&self) -> [u8; 4] {
+        // splitmix64 (Steele, Lea, Flood)
+        let mut z = self.state.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+        z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) as u32).to_ne_bytes()
+    }
+}
+
 /// Mask/unmask a frame.
 #[inline]
 pub fn apply_mask(buf: &mut [u8], mask: [u8; 4]) {
-    apply_mask_fast32(buf, mask)
+    apply_mask_simd(buf, mask)
 }
 
 /// A safe unoptimized mask application.
 #[inline]
-fn apply_mask_fallback(buf: &mut [u8], mask: [u8; 4]) {
+pub fn apply_mask_fallback(buf: &mut [u8], mask: [u8; 4]) {
     for (i, byte) in buf.iter_mut().enumerate() {
         *byte ^= mask[i & 3];
     }
@@ -41,6 +143,180 @@ pub fn apply_mask_fast32(buf: &mut [u8], mask: [u8; 4]) {
     apply_mask_fallback(suffix, mask_u32.to_ne_bytes());
 }
 
+/// Vectorized version of `apply_mask()` using the widest SIMD instructions the CPU supports.
+///
+/// On x86_64 AVX2 is detected at runtime, SSE2 is always available. On little-endian aarch64
+/// NEON is part of the target. Other targets use `apply_mask_fast32()`.
+#[inline]
+pub fn apply_mask_simd(buf: &mut [u8], mask: [u8; 4]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if has_avx2() {
+            // SAFETY: AVX2 support was just checked.
+            unsafe { x86_64::apply_mask_avx2(buf, mask) }
+        } else {
+            // SAFETY: SSE2 is part of the x86_64 baseline.
+            unsafe { x86_64::apply_mask_sse2(buf, mask) }
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", target_endian = "little", target_feature = "neon"))]
+    {
+        // SAFETY: NEON is enabled for the target.
+        unsafe { aarch64::apply_mask_neon(buf, mask) }
+    }
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little", target_feature = "neon")
+    )))]
+    {
+        apply_mask_fast32(buf, mask)
+    }
+}
+
+/// Mask `src` while copying it into `dst`, leaving `src` untouched.
+///
+/// Compared to a copy followed by `apply_mask()`, the data is only touched once.
+///
+/// # Panics
+/// Panics if `dst` and `src` differ in length.
+#[inline]
+pub fn apply_mask_copy(dst: &mut [u8], src: &[u8], mask: [u8; 4]) {
+    assert_eq!(dst.len(), src.len(), "Bug: mask destination and source differ in length");
+    #[cfg(target_arch = "x86_64")]
+    {
+        if has_avx2() {
+            // SAFETY: AVX2 support was just checked.
+            unsafe { x86_64::apply_mask_copy_avx2(dst, src, mask) }
+        } else {
+            // SAFETY: SSE2 is part of the x86_64 baseline.
+            unsafe { x86_64::apply_mask_copy_sse2(dst, src, mask) }
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", target_endian = "little", target_feature = "neon"))]
+    {
+        // SAFETY: NEON is enabled for the target.
+        unsafe { aarch64::apply_mask_copy_neon(dst, src, mask) }
+    }
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little", target_feature = "neon")
+    )))]
+    {
+        apply_mask_copy_fast32(dst, src, mask)
+    }
+}
+
+/// Whether AVX2 can be used: detected at runtime with `std`, fixed at compile time without.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+fn has_avx2() -> bool {
+    #[cfg(feature = "std")]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        cfg!(target_feature = "avx2")
+    }
+}
+
+/// Version of `apply_mask_copy()` which operates on 4-byte blocks.
+#[inline]
+fn apply_mask_copy_fast32(dst: &mut [u8], src: &[u8], mask: [u8; 4]) {
+    let mask_u32 = u32::from_ne_bytes(mask);
+    let mut dst_words = dst.chunks_exact_mut(4);
+    let mut src_words = src.chunks_exact(4);
+    for (d, s) in (&mut dst_words).zip(&mut src_words) {
+        let word = u32::from_ne_bytes([s[0], s[1], s[2], s[3]]) ^ mask_u32;
+        d.copy_from_slice(&word.to_ne_bytes());
+    }
+    let tail = dst_words.into_remainder().iter_mut().zip(src_words.remainder());
+    for (i, (d, s)) in tail.enumerate() {
+        *d = s ^ mask[i];
+    }
+}
+
+// The vector implementations XOR whole chunks with the mask repeated across the register. As the
+// chunk sizes are multiples of 4, the remainder starts at the same mask offset as the buffer.
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use core::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn apply_mask_sse2(buf: &mut [u8], mask: [u8; 4]) {
+        let mask_vec = _mm_set1_epi32(i32::from_ne_bytes(mask));
+        let mut chunks = buf.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(ptr, _mm_xor_si128(_mm_loadu_si128(ptr), mask_vec));
+        }
+        super::apply_mask_fast32(chunks.into_remainder(), mask);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn apply_mask_avx2(buf: &mut [u8], mask: [u8; 4]) {
+        let mask_vec = _mm256_set1_epi32(i32::from_ne_bytes(mask));
+        let mut chunks = buf.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr() as *mut __m256i;
+            _mm256_storeu_si256(ptr, _mm256_xor_si256(_mm256_loadu_si256(ptr), mask_vec));
+        }
+        apply_mask_sse2(chunks.into_remainder(), mask);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn apply_mask_copy_sse2(dst: &mut [u8], src: &[u8], mask: [u8; 4]) {
+        let mask_vec = _mm_set1_epi32(i32::from_ne_bytes(mask));
+        let mut dst_chunks = dst.chunks_exact_mut(16);
+        let mut src_chunks = src.chunks_exact(16);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            let data = _mm_loadu_si128(s.as_ptr() as *const __m128i);
+            _mm_storeu_si128(d.as_mut_ptr() as *mut __m128i, _mm_xor_si128(data, mask_vec));
+        }
+        super::apply_mask_copy_fast32(dst_chunks.into_remainder(), src_chunks.remainder(), mask);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn apply_mask_copy_avx2(dst: &mut [u8], src: &[u8], mask: [u8; 4]) {
+        let mask_vec = _mm256_set1_epi32(i32::from_ne_bytes(mask));
+        let mut dst_chunks = dst.chunks_exact_mut(32);
+        let mut src_chunks = src.chunks_exact(32);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            let data = _mm256_loadu_si256(s.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(d.as_mut_ptr() as *mut __m256i, _mm256_xor_si256(data, mask_vec));
+        }
+        apply_mask_copy_sse2(dst_chunks.into_remainder(), src_chunks.remainder(), mask);
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_endian = "little", target_feature = "neon"))]
+mod aarch64 {
+    use core::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn apply_mask_neon(buf: &mut [u8], mask: [u8; 4]) {
+        let mask_vec = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_ne_bytes(mask)));
+        let mut chunks = buf.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr();
+            vst1q_u8(ptr, veorq_u8(vld1q_u8(ptr), mask_vec));
+        }
+        super::apply_mask_fast32(chunks.into_remainder(), mask);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn apply_mask_copy_neon(dst: &mut [u8], src: &[u8], mask: [u8; 4]) {
+        let mask_vec = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_ne_bytes(mask)));
+        let mut dst_chunks = dst.chunks_exact_mut(16);
+        let mut src_chunks = src.chunks_exact(16);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            vst1q_u8(d.as_mut_ptr(), veorq_u8(vld1q_u8(s.as_ptr()), mask_vec));
+        }
+        super::apply_mask_copy_fast32(dst_chunks.into_remainder(), src_chunks.remainder(), mask);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +346,77 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_apply_mask_simd() {
+        let mask = [0x6d, 0xb6, 0xb2, 0x80];
+        let unmasked: Vec<u8> = (0..=255).collect();
+
+        // Cover every remainder of the 16 and 32 byte chunks at every mask offset.
+        for data_len in (0..80).chain(250..=256) {
+            for off in 0..=3 {
+                if data_len < off {
+                    continue;
+                }
+                let mut masked = unmasked[..data_len].to_vec();
+                apply_mask_fallback(&mut masked[off..], mask);
+
+                let mut masked_simd = unmasked[..data_len].to_vec();
+                apply_mask_simd(&mut masked_simd[off..], mask);
+
+                assert_eq!(masked, masked_simd);
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_apply_mask_sse2() {
+        let mask = [0x01, 0x02, 0x03, 0x04];
+        let mut masked: Vec<u8> = (0..100).collect();
+        let mut masked_sse2 = masked.clone();
+        apply_mask_fallback(&mut masked[3..], mask);
+        // SAFETY: SSE2 is part of the x86_64 baseline.
+        unsafe { x86_64::apply_mask_sse2(&mut masked_sse2[3..], mask) };
+        assert_eq!(masked, masked_sse2);
+    }
+
+    #[test]
+    fn test_apply_mask_copy() {
+        let mask = [0x6d, 0xb6, 0xb2, 0x80];
+        let unmasked: Vec<u8> = (0..=255).collect();
+
+        for data_len in (0..80).chain(250..=256) {
+            for off in 0..=3 {
+                if data_len < off {
+                    continue;
+                }
+                let src = &unmasked[off..data_len];
+                let mut masked = src.to_vec();
+                apply_mask_fallback(&mut masked, mask);
+
+                let mut copied = vec![0; src.len()];
+                apply_mask_copy(&mut copied, src, mask);
+                assert_eq!(masked, copied);
+
+                let mut copied_fast = vec![0; src.len()];
+                apply_mask_copy_fast32(&mut copied_fast, src, mask);
+                assert_eq!(masked, copied_fast);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mask_generators() {
+        static SEEDED: SeededMask = SeededMask::new(7);
+        let first = SEEDED.generate_mask();
+        assert_ne!(first, SEEDED.generate_mask());
+        let replay = SeededMask::new(7);
+        assert_eq!(first, replay.generate_mask());
+
+        assert_eq!(FixedMask([1, 2, 3, 4]).generate_mask(), [1, 2, 3, 4]);
+
+        let masks: Vec<_> = (0..8).map(|_| FastMask.generate_mask()).collect();
+        assert!(masks.windows(2).any(|pair| pair[0] != pair[1]));
+    }
 }
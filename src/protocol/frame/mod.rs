@@ -4,16 +4,35 @@ pub mod coding;
 
 #[allow(clippy::module_inception)]
 mod frame;
-mod mask;
+// Public for the benchmarks.
+#[doc(hidden)]
+pub mod mask;
 
+#[cfg(feature = "std")]
+use crate::storage::BufferPool;
 use crate::{
-    error::{Error, Result},
-    Message, storage::U8RingBuffer,
+    error::{CapacityError, Error, Result},
+    io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write},
+    storage::{managed_vec::ManagedVec, U8RingBuffer},
+    Message,
 };
-use log::*;
-use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::mem;
 
-pub use self::frame::{CloseFrame, FastWriteFrame,ReadFrame, WriteFrame,FrameHeader};
+use super::logging::trace;
+
+pub use self::frame::{
+    CloseFrame, CloseReason, FastWriteFrame, FrameHeader, Payload, ReadFrame, WriteFrame,
+    MAX_CONTROL_PAYLOAD_LEN,
+};
+pub(crate) use self::frame::STATIC_SCRATCH_LEN;
+pub use self::mask::{FixedMask, MaskGenerator};
+#[cfg(feature = "std")]
+pub use self::mask::{FastMask, SecureMask};
+#[cfg(target_has_atomic = "64")]
+pub use self::mask::SeededMask;
 
 /// A reader and writer for WebSocket frames.
 #[derive(Debug)]
@@ -26,11 +45,13 @@ pub struct FrameSocket<'a,Stream> {
 
 impl<'a,Stream> FrameSocket<'a,Stream> {
     /// Create a new frame socket.
+    #[cfg(feature = "alloc")]
     pub fn new(stream: Stream) -> Self {
         FrameSocket { stream, codec: FrameCodec::new() }
     }
 
     /// Create a new frame socket from partially read data.
+    #[cfg(feature = "alloc")]
     pub fn from_partially_read(stream: Stream, part: Vec<u8>) -> Self {
         FrameSocket { stream, codec: FrameCodec::from_partially_read(part) }
     }
@@ -56,7 +77,7 @@ where
     Stream: Read,
 {
     /// Read a frame from stream.
-    pub fn read(&mut self) -> Result<Option<&mut ReadFrame>> {
+    pub fn read(&mut self) -> Result<Option<ReadFrame<'_>>> {
         self.codec.read_frame(&mut self.stream)
     }
 }
@@ -93,10 +114,16 @@ where
 /// A codec for WebSocket frames.
 #[derive(Debug)]
 pub(super) struct FrameCodec<'a> {
-    /// Buffer to read data from the stream.
+    /// Buffer to read data from the stream. Without storage until the first read.
     in_buffer: U8RingBuffer<'a>,
+    /// Pool to borrow `in_buffer` from, see [`Self::set_buffer_pool`].
+    #[cfg(feature = "std")]
+    buffer_pool: Option<&'static BufferPool>,
+    /// The pool that lent the current `in_buffer`, if any.
+    #[cfg(feature = "std")]
+    lender: Option<&'static BufferPool>,
     /// Buffer to send packets to the network.
-    out_buffer: Vec<u8>,
+    out_buffer: ManagedVec<'a, u8>,
     /// Capacity limit for `out_buffer`.
     max_out_buffer_len: usize,
     /// Buffer target length to reach before writing to the stream
@@ -107,32 +134,61 @@ pub(super) struct FrameCodec<'a> {
     out_buffer_write_len: usize,
     /// Header and remaining size of the incoming packet being processed.
     header: Option<(FrameHeader, u64)>,
-    /// frame 
-    reuse_frame : ReadFrame
+    /// Payload length of the last frame read, which stays in `in_buffer` until the next read.
+    last_payload_len: usize,
 }
 
 impl<'a> FrameCodec<'a> {
     /// Create a new frame codec.
+    #[cfg(feature = "alloc")]
     pub(super) fn new() -> Self {
-        Self {
-            in_buffer: U8RingBuffer::new(),
-            out_buffer: Vec::new(),
-            max_out_buffer_len: usize::MAX,
-            out_buffer_write_len: 0,
-            header: None,
-            reuse_frame: ReadFrame::new()
-        }
+        Self::from_read_buffer(U8RingBuffer::empty())
     }
 
     /// Create a new frame codec from partially read data.
+    ///
+    /// # Panics
+    /// Panics if `part` does not fit into the read buffer.
+    #[cfg(feature = "alloc")]
     pub(super) fn from_partially_read(part: Vec<u8>) -> Self {
+        let mut in_buffer = U8RingBuffer::new();
+        assert_eq!(in_buffer.0.enqueue_slice(&part), part.len(), "Partially read data too long");
+        Self::from_read_buffer(in_buffer)
+    }
+
+    /// Create a new frame codec that continues with the data left in the given buffer.
+    ///
+    /// A buffer with data left is kept as the read buffer, grown to the usual size if it is
+    /// smaller, so the data is not copied. An empty one is dropped, and the codec allocates a
+    /// read buffer of its own on the first read.
+    #[cfg(feature = "alloc")]
+    pub(super) fn from_read_buffer(mut tail: U8RingBuffer<'a>) -> Self {
+        let in_buffer = if tail.0.is_empty() {
+            U8RingBuffer::empty()
+        } else {
+            tail.grow_to_default();
+            tail
+        };
+        Self::from_storage(in_buffer, ManagedVec::new())
+    }
+
+    /// Create a new frame codec that reads into `in_buffer` and writes from `out_buffer`,
+    /// without allocating if they are both borrowed.
+    pub(super) fn from_storage(
+        in_buffer: U8RingBuffer<'a>,
+        out_buffer: ManagedVec<'a, u8>,
+    ) -> Self {
         Self {
-            in_buffer: U8RingBuffer::new(),
-            out_buffer: Vec::new(),
+            in_buffer,
+            #[cfg(feature = "std")]
+            buffer_pool: None,
+            #[cfg(feature = "std")]
+            lender: None,
+            out_buffer,
             max_out_buffer_len: usize::MAX,
             out_buffer_write_len: 0,
             header: None,
-            reuse_frame: ReadFrame::new()
+            last_payload_len: 0,
         }
     }
 
@@ -147,17 +203,80 @@ impl<'a> FrameCodec<'a> {
         self.out_buffer_write_len = len;
     }
 
+    /// Borrow the read buffer from `pool` whenever there is something to read, and give it
+    /// back as soon as everything read has been parsed. `None` keeps one buffer for good.
+    #[cfg(feature = "std")]
+    pub(super) fn set_buffer_pool(&mut self, pool: Option<&'static BufferPool>) {
+        self.buffer_pool = pool;
+        self.return_in_buffer();
+    }
+
+    /// Consume the last frame and give the read buffer back to its pool if nothing else is
+    /// buffered. Returns whether the codec reads from a pool and is idle like that.
+    #[cfg(feature = "std")]
+    pub(super) fn release_idle_buffers(&mut self) -> bool {
+        self.consume_last_frame();
+        self.buffer_pool.is_some() && self.in_buffer.0.capacity() == 0
+    }
+
+    /// Consume the payload of the last frame read, which must not be borrowed anymore.
+    fn consume_last_frame(&mut self) {
+        if self.last_payload_len != 0 {
+            self.in_buffer.0.consume(self.last_payload_len);
+            self.last_payload_len = 0;
+        }
+        self.return_in_buffer();
+    }
+
+    /// Make sure `in_buffer` has storage to read into. Without `alloc` it always has, as it
+    /// is borrowed from [`StaticBuffers`](crate::storage::StaticBuffers).
+    fn prepare_in_buffer(&mut self) {
+        if self.in_buffer.0.capacity() == 0 {
+            #[cfg(feature = "std")]
+            if let Some(pool) = self.buffer_pool {
+                self.lender = Some(pool);
+                self.in_buffer = pool.acquire();
+                return;
+            }
+            #[cfg(feature = "alloc")]
+            {
+                self.in_buffer = U8RingBuffer::new();
+            }
+        }
+    }
+
+    /// Drop the storage of `in_buffer` if it is empty and the codec reads from a pool.
+    #[cfg(not(feature = "std"))]
+    fn return_in_buffer(&mut self) {}
+
+    /// Drop the storage of `in_buffer` if it is empty and the codec reads from a pool.
+    #[cfg(feature = "std")]
+    fn return_in_buffer(&mut self) {
+        if self.buffer_pool.is_none() && self.lender.is_none() {
+            return;
+        }
+        if self.in_buffer.0.is_empty() && self.in_buffer.0.capacity() != 0 {
+            let buffer = mem::replace(&mut self.in_buffer, U8RingBuffer::empty());
+            if let Some(pool) = self.lender.take() {
+                pool.release(buffer);
+            }
+        }
+    }
+
     /// Read a frame from the provided stream.
+    ///
+    /// The payload is borrowed from the read buffer and only consumed on the next call.
     pub(super) fn read_frame<Stream>(
         &mut self,
         stream: &mut Stream,
         // max_size: Option<usize>,
-    ) -> Result<Option<&mut ReadFrame>>
+    ) -> Result<Option<ReadFrame<'_>>>
     where
         Stream: Read,
     {
         //let max_size = max_size.unwrap_or_else(usize::max_value);
 
+        self.consume_last_frame();
         loop {
             {
                 if self.header.is_none() {
@@ -177,27 +296,25 @@ impl<'a> FrameCodec<'a> {
                     // }
                     let input_size = self.in_buffer.0.len();
                     if length <= input_size {
-                        // No truncation here since `length` is checked above
-                        if length > 0 {
-                            if length > self.in_buffer.0.contigous_len(){
-
-                                self.reuse_frame.set_payload(self.in_buffer.0.dequeue_many_leap(length));
-                            }else{
-                                self.reuse_frame.set_payload(self.in_buffer.0.dequeue_many_no_leap(length))
-                            }
-                           // self.reuse_frame.set_payload(self.in_buffer.dequeue_many_contiguous(length as usize));
-                        }
+                        // No truncation here since `length` is checked above.
+                        self.last_payload_len = length;
                         break;
                     }
                 }   
             }
 
             //Not enough data in buffer.
-            let size = self.in_buffer.0.enqueue_many_with(|buf| {
-                let size = stream.read(buf).unwrap();
-                (size, &mut buf[..size])
-            })
-            .0;
+            self.prepare_in_buffer();
+            if self.in_buffer.0.is_full() {
+                let size = self.header.as_ref().map_or(0, |&(_, length)| length as usize);
+                let max_size = self.in_buffer.0.capacity();
+                return Err(Error::Capacity(CapacityError::MessageTooLong { size, max_size }));
+            }
+            let read = self.in_buffer.read_from(stream);
+            if !matches!(read, Ok(size) if size > 0) {
+                self.return_in_buffer();
+            }
+            let size = read?;
 
             if size == 0 {
                 trace!("no frame received");
@@ -207,9 +324,11 @@ impl<'a> FrameCodec<'a> {
 
         let (header, _) = self.header.take().expect("Bug: no frame header");
         //debug_assert_eq!(payload.len() as u64, length);
-        self.reuse_frame.set_header(header);
+        let mut frame = ReadFrame::new();
+        frame.set_header(header);
+        frame.set_payload(self.in_buffer.0.peek_contiguous_mut(self.last_payload_len));
         //trace!("received frame {}", frame);
-        Ok(Some(&mut self.reuse_frame))
+        Ok(Some(frame))
     }
 
     /// Writes a frame into the `out_buffer`.
@@ -223,7 +342,16 @@ impl<'a> FrameCodec<'a> {
     where
         Stream: Write,
     {
-        if frame.len() + self.out_buffer.len() > self.max_out_buffer_len {
+        // Make room in a borrowed buffer before giving up on the frame. If the stream blocks,
+        // the frame is handed back below, so pongs and close replies can be retried.
+        if frame.len() + self.out_buffer.len() > self.out_buffer.max_len() {
+            match self.write_out_buffer(stream) {
+                Err(Error::Io(err)) if err.kind() == IoErrorKind::WouldBlock => {}
+                result => result?,
+            }
+        }
+        let max_len = self.max_out_buffer_len.min(self.out_buffer.max_len());
+        if frame.len() + self.out_buffer.len() > max_len {
             return Err(Error::WriteBufferFull(Message::WriteFrame(frame)));
         }
 
@@ -256,18 +384,140 @@ impl<'a> FrameCodec<'a> {
                 )
                 .into());
             }
-            self.out_buffer.drain(0..len);
+            self.out_buffer.drain_front(len);
         }
 
         Ok(())
     }
 }
 
+#[cfg(feature = "std")]
+impl Drop for FrameCodec<'_> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.lender.take() {
+            pool.release(mem::replace(&mut self.in_buffer, U8RingBuffer::empty()));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // use crate::error::{CapacityError, Error};
 
-    // use std::io::Cursor;
+    use super::{FrameCodec, FrameSocket};
+    use crate::storage::{BufferPool, U8RingBuffer};
+    use std::io::Cursor;
+
+    #[test]
+    fn read_frames_across_wraparound() {
+        // 43 frames of 1524 bytes fill the 64 KiB read buffer up to 4 bytes before its end, so
+        // the header and the payload of the next frame wrap around.
+        let mut raw = Vec::new();
+        for i in 0..50u8 {
+            raw.extend_from_slice(&[0x82, 0xfe, 0x05, 0xec, 1, 2, 3, i]);
+            raw.extend(std::iter::repeat(i).take(1516));
+        }
+        let mut sock = FrameSocket::new(Cursor::new(raw));
+        for i in 0..50u8 {
+            let frame = sock.read().unwrap().unwrap();
+            assert_eq!(frame.header().mask, Some([1, 2, 3, i]));
+            assert_eq!(frame.payload(), &[i; 1516][..]);
+        }
+        assert!(sock.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn borrow_read_buffer_from_pool() {
+        let pool: &'static BufferPool = Box::leak(Box::new(BufferPool::new(1024)));
+        let mut codec = FrameCodec::new();
+        codec.set_buffer_pool(Some(pool));
+        assert_eq!(codec.in_buffer.0.capacity(), 0);
+
+        // The buffer is kept while a frame is partially received.
+        let mut stream = Cursor::new(vec![0x82, 0x05, 1, 2]);
+        assert!(codec.read_frame(&mut stream).unwrap().is_none());
+        assert_eq!(pool.stats().in_use, 1);
+
+        stream.get_mut().extend_from_slice(&[3, 4, 5, 0x82, 0x01]);
+        assert_eq!(codec.read_frame(&mut stream).unwrap().unwrap().payload(), &[1, 2, 3, 4, 5]);
+        assert_eq!(pool.stats().in_use, 1);
+
+        // And while the payload of the last frame is borrowed from it.
+        stream.get_mut().push(6);
+        assert_eq!(codec.read_frame(&mut stream).unwrap().unwrap().payload(), &[6]);
+        assert_eq!(pool.stats().in_use, 1);
+
+        // It is returned once everything read has been parsed and consumed.
+        assert!(codec.read_frame(&mut stream).unwrap().is_none());
+        assert_eq!(pool.stats().in_use, 0);
+        assert_eq!(codec.in_buffer.0.capacity(), 0);
+
+        stream.get_mut().push(0x82);
+        assert!(codec.read_frame(&mut stream).unwrap().is_none());
+        assert_eq!(pool.stats().in_use, 1);
+        drop(codec);
+        assert_eq!(pool.stats().in_use, 0);
+        assert_eq!(pool.stats().allocated, 1);
+    }
+
+    #[test]
+    fn release_idle_buffers() {
+        let pool: &'static BufferPool = Box::leak(Box::new(BufferPool::new(1024)));
+        let mut stream = Cursor::new(vec![0x82, 0x02, 1, 2, 0x82, 0x01, 3]);
+        let mut codec = FrameCodec::new();
+        codec.set_buffer_pool(Some(pool));
+        assert_eq!(codec.read_frame(&mut stream).unwrap().unwrap().payload(), &[1, 2]);
+
+        // The next frame is still buffered.
+        assert!(!codec.release_idle_buffers());
+        assert_eq!(pool.stats().in_use, 1);
+
+        // The payload of the last frame is consumed, so nothing is left.
+        assert_eq!(codec.read_frame(&mut stream).unwrap().unwrap().payload(), &[3]);
+        assert!(codec.release_idle_buffers());
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn borrow_payload_from_read_buffer() {
+        // The second payload wraps around the end of the read buffer, and is made contiguous
+        // in place (or borrowed from the mirror) without a copy of its own.
+        let mut raw = vec![0x82, 0x7e, 0xff, 0xf0];
+        raw.extend(std::iter::repeat(1).take(0xfff0));
+        raw.extend_from_slice(&[0x82, 0x7e, 0x01, 0x00]);
+        raw.extend((0..=255).map(|i| i as u8));
+        let mut sock = FrameSocket::new(Cursor::new(raw));
+        assert_eq!(sock.read().unwrap().unwrap().payload(), &[1; 0xfff0][..]);
+        let mut frame = sock.read().unwrap().unwrap();
+        frame.payload_mut()[0] = 42;
+        assert_eq!(frame.payload()[..3], [42, 1, 2]);
+        assert_eq!(frame.payload()[255], 255);
+        assert!(sock.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn continue_from_handshake_buffer() {
+        // Nothing is kept from an empty handshake buffer.
+        let codec = FrameCodec::from_read_buffer(U8RingBuffer::with_capacity(16));
+        assert_eq!(codec.in_buffer.0.capacity(), 0);
+
+        // A buffer with data left is kept without copying the data.
+        let mut tail = U8RingBuffer::with_capacity(65536);
+        assert_eq!(tail.0.enqueue_slice(&[0x82, 0x03, 1]), 3);
+        let data = tail.0.get_allocated(0, 3).as_ptr();
+        let mut codec = FrameCodec::from_read_buffer(tail);
+        assert_eq!(codec.in_buffer.0.get_allocated(0, 3).as_ptr(), data);
+
+        let mut stream = Cursor::new(vec![2, 3]);
+        assert_eq!(codec.read_frame(&mut stream).unwrap().unwrap().payload(), &[1, 2, 3]);
+
+        // And grown if it is smaller than the usual read buffer.
+        let mut tail = U8RingBuffer::with_capacity(16);
+        assert_eq!(tail.0.enqueue_slice(&[0x82, 0x03, 1]), 3);
+        let codec = FrameCodec::from_read_buffer(tail);
+        assert_eq!(codec.in_buffer.0.capacity(), 65536);
+        assert_eq!(codec.in_buffer.to_vec(), [0x82, 0x03, 1]);
+    }
 
     // #[test]
     // fn read_frames() {
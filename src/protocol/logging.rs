@@ -0,0 +1,25 @@
+//! Logging of the protocol layer.
+//!
+//! The macros forward to `defmt` if the `defmt` feature is enabled and to `log` otherwise, so
+//! their arguments have to implement both `defmt::Format` and the `core::fmt` trait of the
+//! placeholder.
+
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::trace!($($arg)*);
+        #[cfg(not(feature = "defmt"))]
+        log::trace!($($arg)*);
+    }};
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::debug!($($arg)*);
+        #[cfg(not(feature = "defmt"))]
+        log::debug!($($arg)*);
+    }};
+}
+
+pub(crate) use {debug, trace};
@@ -1,22 +1,34 @@
 
-use std::{
-    convert::{ From, Into, TryFrom},
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::convert::TryFrom;
+use core::{
     fmt,
     result::Result as StdResult,
-    str, io::Read
+    str,
 };
 
-use super::frame::{CloseFrame, ReadFrame,WriteFrame};
-use crate::error::{ Error, Result};
+use super::frame::{CloseFrame, Payload, ReadFrame, WriteFrame};
+use crate::{
+    error::{CapacityError, Error, Result},
+    io::{self, Read},
+    storage::managed_vec::ManagedVec,
+};
 
 #[derive(Debug)]
-pub struct BitCollector{
-    data:Vec<u8>
+pub struct BitCollector<'a> {
+    data: ManagedVec<'a, u8>
 }
 
-impl BitCollector{
-    pub fn new() -> BitCollector{    
-        BitCollector { data: Vec::with_capacity(500)}
+impl<'a> BitCollector<'a> {
+    #[cfg(feature = "alloc")]
+    pub fn new() -> Self {
+        BitCollector { data: ManagedVec::with_capacity(500)}
+    }
+    /// Collect into `storage` instead of an allocated buffer.
+    pub fn with_storage(storage: &'a mut [u8]) -> Self {
+        BitCollector { data: ManagedVec::from(storage) }
     }
     #[inline(always)]
     pub fn len(&self) -> usize {
@@ -25,51 +37,95 @@ impl BitCollector{
     #[inline(always)]
     pub fn extend(&mut self, tail: &mut ReadFrame) -> Result<()> {
         let payload :&[u8] = tail.payload();
-        self.data.extend(payload);
-        Ok(())
+        self.data.extend_from_slice(payload).map_err(|_| {
+            Error::Capacity(CapacityError::MessageTooLong {
+                size: self.data.len().saturating_add(payload.len()),
+                max_size: self.data.max_len(),
+            })
+        })
     }
     #[inline(always)]
     pub fn reuse(&mut self){
         self.data.clear()
     }
-    pub fn read(&self,buf: &mut[u8])->std::io::Result<usize> {
+    /// Drop the collected data and free its buffer, unless it is borrowed.
+    #[cfg(feature = "std")]
+    pub fn release(&mut self) {
+        self.data.release()
+    }
+    /// The number of bytes that can be collected without allocating.
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+    pub fn read(&self,buf: &mut[u8])->io::Result<usize> {
         let len = self.data.len();
         if buf.len() < len{
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Buffer is too small"));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Buffer is too small"));
         }
-        buf[..len].copy_from_slice(self.data.as_slice());
+        buf[..len].copy_from_slice(&self.data);
         Ok(len)    
     }
+    #[cfg(feature = "alloc")]
     pub fn into_vec(&self)-> Vec<u8> {
-        self.data.clone()
+        self.data.to_vec()
     }
+    #[cfg(feature = "alloc")]
     pub fn into_string(&self) -> Result<String> {
-        Ok(String::from_utf8(self.data.clone())?)
+        Ok(String::from_utf8(self.data.to_vec())?)
     }
 }
 
 /// A struct representing the incomplete message.
 #[derive(Debug)]
-pub struct IncompleteMessage {
+pub struct IncompleteMessage<'a> {
     msg_type : IncompleteMessageType,
-    collector: BitCollector
+    collector: BitCollector<'a>,
+    /// Whether a message is being collected, i.e. not all of its frames arrived yet.
+    collecting: bool,
 }
 
-impl IncompleteMessage {
+impl<'a> IncompleteMessage<'a> {
     /// Create new.
+    #[cfg(feature = "alloc")]
     pub fn new() -> Self {
         IncompleteMessage {
             collector:BitCollector::new(),
             msg_type: IncompleteMessageType::Text,
+            collecting: false,
+        }
+    }
+
+    /// Create a message that is collected into `storage` instead of an allocated buffer.
+    pub fn with_storage(storage: &'a mut [u8]) -> Self {
+        IncompleteMessage {
+            collector: BitCollector::with_storage(storage),
+            msg_type: IncompleteMessageType::Text,
+            collecting: false,
         }
     }
 
     /// Create new.
-    pub fn reuse(&mut self,msg_type:IncompleteMessageType) ->&mut IncompleteMessage{
+    pub fn reuse(&mut self,msg_type:IncompleteMessageType) ->&mut IncompleteMessage<'a>{
         self.msg_type = msg_type;
         self.collector.reuse();
+        self.collecting = true;
         self
     }
+
+    /// Free the buffer of the last completed message, unless it is borrowed. A message that
+    /// is still being collected is kept.
+    #[cfg(feature = "std")]
+    pub fn release(&mut self) {
+        if !self.collecting {
+            self.collector.release();
+        }
+    }
+
+    /// The number of bytes that can be collected without allocating.
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.collector.capacity()
+    }
     
     // /// Create new.
     // pub fn new(msg_type:IncompleteMessageType) -> Self {
@@ -102,16 +158,11 @@ impl IncompleteMessage {
     }
 
     /// Convert an incomplete message into a complete one.
-    pub fn complete(&mut self) -> Result<Message> {
+    pub fn complete(&mut self) -> Result<Message<'_>> {
+        self.collecting = false;
         match self.msg_type {
-            IncompleteMessageType::Binary => {
-                let non_null_bit_collector = &mut self.collector;
-                Ok(Message::ReadBinary(non_null_bit_collector))
-            }
-            IncompleteMessageType::Text => {
-                let non_null_bit_collector = &mut self.collector;
-                Ok(Message::ReadText(non_null_bit_collector))
-            }
+            IncompleteMessageType::Binary => Ok(Message::ReadBinary(&self.collector)),
+            IncompleteMessageType::Text => Ok(Message::ReadText(&self.collector)),
         }
     }
 }
@@ -125,46 +176,41 @@ pub enum IncompleteMessageType {
 
 /// An enum representing the various forms of a WebSocket message.
 #[derive(Debug)]
-pub enum Message {
+pub enum Message<'a> {
     /// A text WebSocket message
+    #[cfg(feature = "alloc")]
     Text(String),
     /// A binary WebSocket message
+    #[cfg(feature = "alloc")]
     Binary(Vec<u8>),
-    /// A text WebSocket message
-    ReadText(*mut BitCollector),
-    /// A binary WebSocket message
-    ReadBinary(*mut BitCollector),
+    /// A text WebSocket message, borrowed from the context until the next read
+    ReadText(&'a BitCollector<'a>),
+    /// A binary WebSocket message, borrowed from the context until the next read
+    ReadBinary(&'a BitCollector<'a>),
     /// A ping message with the specified payload
     ///
     /// The payload here must have a length less than 125 bytes
-    Ping(Vec<u8>),
+    Ping(Payload),
     /// A pong message with the specified payload
     ///
     /// The payload here must have a length less than 125 bytes
-    Pong(Vec<u8>),
+    Pong(Payload),
     /// A close message with the optional close frame.
     Close(Option<CloseFrame<'static>>),
     /// Raw frame. Note, that you're not going to get this value while reading the message.
-    ReadFrame(ReadFrame),
+    ReadFrame(ReadFrame<'a>),
     /// Raw frame. Note, that you're not going to get this value while reading the message.
     WriteFrame(WriteFrame),
 }
 
-impl Read for Message{
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>{
+impl Read for Message<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>{
         match self{
+            #[cfg(feature = "alloc")]
             Message::Text(_) => todo!(),
+            #[cfg(feature = "alloc")]
             Message::Binary(_) =>  todo!(),
-            Message::ReadText(frame) => {
-               unsafe{
-                  (*(*frame)).read(buf)
-               }
-            },
-            Message::ReadBinary(frame) => {
-                unsafe{
-                    (*(*frame)).read(buf)
-                }
-            },
+            Message::ReadText(frame) | Message::ReadBinary(frame) => frame.read(buf),
             Message::Ping(_) => todo!(),
             Message::Pong(_) => todo!(),
             Message::Close(_) => todo!(),
@@ -174,9 +220,10 @@ impl Read for Message{
     }
 }
 
-impl Message {
+impl<'a> Message<'a> {
     /// Create a new text WebSocket message from a stringable.
-    pub fn text<S>(string: S) -> Message
+    #[cfg(feature = "alloc")]
+    pub fn text<S>(string: S) -> Message<'a>
     where
         S: Into<String>,
     {
@@ -184,7 +231,8 @@ impl Message {
     }
 
     /// Create a new binary WebSocket message by converting to `Vec<u8>`.
-    pub fn binary<B>(bin: B) -> Message
+    #[cfg(feature = "alloc")]
+    pub fn binary<B>(bin: B) -> Message<'a>
     where
         B: Into<Vec<u8>>,
     {
@@ -193,12 +241,22 @@ impl Message {
 
     /// Indicates whether a message is a text message.
     pub fn is_text(&self) -> bool {
-        matches!(*self, Message::Text(_)|Message::ReadText(_))
+        match *self {
+            #[cfg(feature = "alloc")]
+            Message::Text(_) => true,
+            Message::ReadText(_) => true,
+            _ => false,
+        }
     }
 
     /// Indicates whether a message is a binary message.
     pub fn is_binary(&self) -> bool {
-        matches!(*self, Message::Binary(_)|Message::ReadBinary(_))
+        match *self {
+            #[cfg(feature = "alloc")]
+            Message::Binary(_) => true,
+            Message::ReadBinary(_) => true,
+            _ => false,
+        }
     }
 
     /// Indicates whether a message is a ping message.
@@ -218,21 +276,21 @@ impl Message {
 
     /// Get the length of the WebSocket message.
     pub fn len(&self) -> usize {
-        unsafe{
-            match *self {
-                Message::Text(ref string) => string.len(),
-                Message::Binary(ref data) => {
-                    data.len()
-                }
-                Message::Ping(ref data) | Message::Pong(ref data) => {
-                    data.len()
-                }
-                Message::Close(ref data) => data.as_ref().map(|d| d.reason.len()).unwrap_or(0),
-                Message::ReadFrame(ref frame) => frame.len(),
-                Message::WriteFrame(ref frame) => frame.len(),
-                Message::ReadText(frame) => (*frame).len(),
-                Message::ReadBinary(frame) => (*frame).len(),
+        match *self {
+            #[cfg(feature = "alloc")]
+            Message::Text(ref string) => string.len(),
+            #[cfg(feature = "alloc")]
+            Message::Binary(ref data) => {
+                data.len()
             }
+            Message::Ping(ref data) | Message::Pong(ref data) => {
+                data.len()
+            }
+            Message::Close(ref data) => data.as_ref().map(|d| d.reason.len()).unwrap_or(0),
+            Message::ReadFrame(ref frame) => frame.len(),
+            Message::WriteFrame(ref frame) => frame.len(),
+            Message::ReadText(frame) => frame.len(),
+            Message::ReadBinary(frame) => frame.len(),
         }
     }
 
@@ -243,6 +301,7 @@ impl Message {
     }
 
     /// Consume the WebSocket and return it as binary data.
+    #[cfg(feature = "alloc")]
     pub fn into_data(self) -> Vec<u8> {
         match self {
             Message::Text(string) => string.into_bytes(),
@@ -258,6 +317,7 @@ impl Message {
     }
 
     /// Attempt to consume the WebSocket message and convert it to a String.
+    #[cfg(feature = "alloc")]
     pub fn into_text(self) -> Result<String> {
         match self {
             Message::Text(string) => Ok(string),
@@ -274,64 +334,93 @@ impl Message {
         }
     }
 
+    /// Copy a message borrowed from the context into one that owns its data, so the context
+    /// can be used again while it is kept.
+    ///
+    /// Fails with [`Error::Utf8`] if a received text message is not valid UTF-8.
+    #[cfg(feature = "alloc")]
+    pub fn into_owned(self) -> Result<Message<'static>> {
+        Ok(match self {
+            Message::Text(string) => Message::Text(string),
+            Message::Binary(data) => Message::Binary(data),
+            Message::ReadText(collector) => Message::Text(collector.into_string()?),
+            Message::ReadBinary(collector) => Message::Binary(collector.into_vec()),
+            Message::Ping(data) => Message::Ping(data),
+            Message::Pong(data) => Message::Pong(data),
+            Message::Close(frame) => Message::Close(frame),
+            Message::ReadFrame(frame) => Message::ReadFrame(ReadFrame::from_payload(
+                frame.header().clone(),
+                frame.payload().to_vec(),
+            )),
+            Message::WriteFrame(frame) => Message::WriteFrame(frame),
+        })
+    }
+
     /// Attempt to get a &str from the WebSocket message,
     /// this will try to convert binary data to utf8.
     pub fn to_text(&self) -> Result<&str> {
         match *self {
+            #[cfg(feature = "alloc")]
             Message::Text(ref string) => Ok(string),
-            Message::Binary(ref data) | Message::Ping(ref data) | Message::Pong(ref data) => {
-                Ok(str::from_utf8(data)?)
-            }
+            #[cfg(feature = "alloc")]
+            Message::Binary(ref data) => Ok(str::from_utf8(data)?),
+            Message::Ping(ref data) | Message::Pong(ref data) => Ok(str::from_utf8(data)?),
             Message::Close(None) => Ok(""),
             Message::Close(Some(ref frame)) => Ok(&frame.reason),
-            //Message::ReadFrame(ref frame) => Ok(frame.to_text()?),
+            Message::ReadFrame(ref frame) => Ok(str::from_utf8(frame.payload())?),
             Message::WriteFrame(ref frame) => Ok(frame.to_text()?),
-            Message::ReadText(_) => todo!(),
-            Message::ReadBinary(_) => todo!(),
-            Message::ReadFrame(_) => todo!(),
+            Message::ReadText(collector) | Message::ReadBinary(collector) => {
+                Ok(str::from_utf8(&collector.data)?)
+            }
         }
     }
 }
 
-impl From<String> for Message {
+#[cfg(feature = "alloc")]
+impl From<String> for Message<'_> {
     fn from(string: String) -> Self {
         Message::text(string)
     }
 }
 
-impl<'s> From<&'s str> for Message {
+#[cfg(feature = "alloc")]
+impl<'s> From<&'s str> for Message<'_> {
     fn from(string: &'s str) -> Self {
         Message::text(string)
     }
 }
 
-impl<'b> From<&'b [u8]> for Message {
+#[cfg(feature = "alloc")]
+impl<'b> From<&'b [u8]> for Message<'_> {
     fn from(data: &'b [u8]) -> Self {
         Message::binary(data)
     }
 }
 
-impl From<Vec<u8>> for Message {
+#[cfg(feature = "alloc")]
+impl From<Vec<u8>> for Message<'_> {
     fn from(data: Vec<u8>) -> Self {
         Message::binary(data)
     }
 }
 
-impl From<Message> for Vec<u8> {
-    fn from(message: Message) -> Self {
+#[cfg(feature = "alloc")]
+impl From<Message<'_>> for Vec<u8> {
+    fn from(message: Message<'_>) -> Self {
         message.into_data()
     }
 }
 
-impl TryFrom<Message> for String {
+#[cfg(feature = "alloc")]
+impl TryFrom<Message<'_>> for String {
     type Error = Error;
 
-    fn try_from(value: Message) -> StdResult<Self, Self::Error> {
+    fn try_from(value: Message<'_>) -> StdResult<Self, Self::Error> {
         value.into_text()
     }
 }
 
-impl fmt::Display for Message {
+impl fmt::Display for Message<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> StdResult<(), fmt::Error> {
         if let Ok(string) = self.to_text() {
             write!(f, "{}", string)
@@ -341,6 +430,17 @@ impl fmt::Display for Message {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Message<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        if let Ok(string) = self.to_text() {
+            defmt::write!(f, "{=str}", string)
+        } else {
+            defmt::write!(f, "Binary Data<length={=usize}>", self.len())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -379,6 +479,22 @@ mod tests {
         assert_eq!(bin_copy, serialized);
     }
 
+    #[test]
+    fn display_received() {
+        let mut incomplete = IncompleteMessage::new();
+        let mut frame = ReadFrame::from_payload(Default::default(), b"test".to_vec());
+        incomplete.reuse(IncompleteMessageType::Text).extend(&mut frame).unwrap();
+        assert_eq!(incomplete.complete().unwrap().to_string(), "test");
+        let owned = incomplete.complete().unwrap().into_owned().unwrap();
+        assert!(matches!(owned, Message::Text(ref text) if text == "test"));
+
+        let mut frame = ReadFrame::from_payload(Default::default(), vec![0, 241]);
+        incomplete.reuse(IncompleteMessageType::Binary).extend(&mut frame).unwrap();
+        assert_eq!(incomplete.complete().unwrap().to_string(), "Binary Data<length=2>");
+
+        assert!(matches!(Message::ReadFrame(frame).to_text(), Err(Error::Utf8)));
+    }
+
     #[test]
     fn text_convert() {
         let s = "kiwotsukete";
@@ -2,6 +2,7 @@
 
 pub mod frame;
 
+pub(crate) mod logging;
 mod message;
 
 pub use self::{frame::CloseFrame, message::Message};
@@ -9,23 +10,28 @@ pub use self::{frame::CloseFrame, message::Message};
 use self::{
     frame::{
         coding::{CloseCode, Control as OpCtl, Data as OpData, OpCode},
-         FrameCodec, WriteFrame, FastWriteFrame,
+         FrameCodec, WriteFrame, FastWriteFrame, MaskGenerator,
     },
+    logging::{debug, trace},
     message::{IncompleteMessage, IncompleteMessageType},
 };
+#[cfg(feature = "handshake")]
+use crate::handshake::HandshakeConfig;
+#[cfg(feature = "std")]
+use crate::storage::BufferPool;
 use crate::{
     error::{Error, ProtocolError, Result},
-    util::NonBlockingResult,
-};
-use log::*;
-use std::{
-    //io::{ErrorKind as IoErrorKind, Read, Write},
     io::{Read, Write},
-    mem::replace,
+    storage::{managed_vec::ManagedVec, ring_buffer::RingBuffer, StaticBuffers, U8RingBuffer},
+    util::NonBlockingResult,
 };
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec};
+use core::mem::replace;
 
 /// Indicates a Client or Server role of the websocket
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Role {
     /// This socket is a server
     Server,
@@ -75,6 +81,25 @@ pub struct WebSocketConfig {
     /// some popular libraries that are sending unmasked frames, ignoring the RFC.
     /// By default this option is set to `false`, i.e. according to RFC 6455.
     pub accept_unmasked_frames: bool,
+    /// The generator of the masks of the frames sent by a client. The default is
+    /// [`SecureMask`](frame::SecureMask), a cryptographically secure RNG as required by
+    /// RFC 6455. Without `std` it is `None`: servers never mask, but a client fails to send
+    /// with [`ProtocolError::MissingMaskGenerator`] until one is set.
+    /// [`FixedMask`](frame::FixedMask) or [`SeededMask`](frame::SeededMask) make the output
+    /// of a client reproducible in tests.
+    pub mask_generator: Option<&'static dyn MaskGenerator>,
+    /// A pool to borrow the read buffer from while a frame is partially received. Idle
+    /// connections then hold no read buffer, and each connection buffers at most
+    /// [`BufferPool::buffer_size`] bytes. The default is `None`, every connection keeps a
+    /// 64 KiB read buffer of its own.
+    ///
+    /// With a pool, the buffers of the last frame and message are also freed when `read` is
+    /// called again while nothing is buffered.
+    #[cfg(feature = "std")]
+    pub buffer_pool: Option<&'static BufferPool>,
+    /// The limits and the deadline of the opening handshake, see [`HandshakeConfig`].
+    #[cfg(feature = "handshake")]
+    pub handshake: HandshakeConfig,
 }
 
 impl Default for WebSocketConfig {
@@ -87,6 +112,14 @@ impl Default for WebSocketConfig {
             max_message_size: Some(64 << 20),
             max_frame_size: Some(16 << 20),
             accept_unmasked_frames: false,
+            #[cfg(feature = "std")]
+            mask_generator: Some(&frame::SecureMask),
+            #[cfg(not(feature = "std"))]
+            mask_generator: None,
+            #[cfg(feature = "std")]
+            buffer_pool: None,
+            #[cfg(feature = "handshake")]
+            handshake: HandshakeConfig::default(),
         }
     }
 }
@@ -114,6 +147,9 @@ pub struct WebSocket<'a,Stream> {
     socket: Stream,
     /// The context for managing a WebSocket.
     context: WebSocketContext<'a>,
+    /// User data attached during the handshake.
+    #[cfg(feature = "handshake")]
+    extensions: http::Extensions,
 }
 
 impl<'a,Stream> WebSocket<'a,Stream> {
@@ -125,8 +161,14 @@ impl<'a,Stream> WebSocket<'a,Stream> {
     ///
     /// # Panics
     /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
+    #[cfg(feature = "alloc")]
     pub fn from_raw_socket(stream: Stream, role: Role, config: Option<WebSocketConfig>) -> Self {
-        WebSocket { socket: stream, context: WebSocketContext::new(role, config) }
+        WebSocket {
+            socket: stream,
+            context: WebSocketContext::new(role, config),
+            #[cfg(feature = "handshake")]
+            extensions: http::Extensions::new(),
+        }
     }
 
     /// Convert a raw socket into a WebSocket without performing a handshake.
@@ -136,7 +178,9 @@ impl<'a,Stream> WebSocket<'a,Stream> {
     /// `connect()` or `accept()` functions of the crate to construct a websocket.
     ///
     /// # Panics
-    /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
+    /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`, or if
+    /// `part` does not fit into the read buffer.
+    #[cfg(feature = "alloc")]
     pub fn from_partially_read(
         stream: Stream,
         part: Vec<u8>,
@@ -146,6 +190,24 @@ impl<'a,Stream> WebSocket<'a,Stream> {
         WebSocket {
             socket: stream,
             context: WebSocketContext::from_partially_read(part, role, config),
+            #[cfg(feature = "handshake")]
+            extensions: http::Extensions::new(),
+        }
+    }
+
+    /// Convert a raw socket into a WebSocket that continues with the data the handshake
+    /// read past its end.
+    #[cfg(feature = "handshake")]
+    pub(crate) fn from_read_buffer(
+        stream: Stream,
+        buffer: crate::storage::U8RingBuffer<'a>,
+        role: Role,
+        config: Option<WebSocketConfig>,
+    ) -> Self {
+        WebSocket {
+            socket: stream,
+            context: WebSocketContext::from_read_buffer(buffer, role, config),
+            extensions: http::Extensions::new(),
         }
     }
 
@@ -171,6 +233,31 @@ impl<'a,Stream> WebSocket<'a,Stream> {
         self.context.get_config()
     }
 
+    /// The subprotocol negotiated during the handshake, if any.
+    #[cfg(feature = "alloc")]
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.context.subprotocol()
+    }
+
+    /// Set the negotiated subprotocol once the handshake is done.
+    #[cfg(feature = "handshake")]
+    pub(crate) fn set_subprotocol(&mut self, subprotocol: Option<String>) {
+        self.context.set_subprotocol(subprotocol)
+    }
+
+    /// User data attached to the connection, e.g. by a
+    /// [`StreamCallback`](crate::handshake::server::StreamCallback) during the handshake.
+    #[cfg(feature = "handshake")]
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
+    }
+
+    /// Mutable access to the user data attached to the connection.
+    #[cfg(feature = "handshake")]
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        &mut self.extensions
+    }
+
     /// Check if it is possible to read messages.
     ///
     /// Reading is impossible after receiving `Message::Close`. It is still possible after
@@ -201,7 +288,7 @@ impl<'a,Stream: Read + Write> WebSocket<'a,Stream> {
     /// You should continue calling [`read`](Self::read), [`write`](Self::write) or
     /// [`flush`](Self::flush) to drive the reply to the close frame until [`Error::ConnectionClosed`]
     /// is returned. Once that happens it is safe to drop the underlying connection.
-    pub fn read(&mut self) -> Result<Message> {
+    pub fn read(&mut self) -> Result<Message<'_>> {
         self.context.read(&mut self.socket)
     }
 
@@ -214,6 +301,7 @@ impl<'a,Stream: Read + Write> WebSocket<'a,Stream> {
     
     /// Writes and immediately flushes a message.
     /// fast send,only send text or binary
+    #[cfg(feature = "alloc")]
     pub fn fast_send(&mut self, message: Message) -> Result<()> {
         self.fast_write(message)?;
         self.fast_flush()
@@ -221,7 +309,7 @@ impl<'a,Stream: Read + Write> WebSocket<'a,Stream> {
 
     /// Writes and immediately flushes a message.
     /// fast send,only send text or binary
-    pub fn fast_send_text_slice(&mut self, data: &mut[u8]) -> Result<()> {
+    pub fn fast_send_text_slice(&mut self, data: &[u8]) -> Result<()> {
         self.fast_write_text_slice(data)?;
         self.fast_flush()
     }
@@ -264,11 +352,12 @@ impl<'a,Stream: Read + Write> WebSocket<'a,Stream> {
         self.context.write(&mut self.socket, message)
     }
 
+    #[cfg(feature = "alloc")]
     pub fn fast_write(&mut self, message: Message) -> Result<()> {
         self.context.fast_write(&mut self.socket, message).check_connection_reset(&self.context.state)
     }
 
-    pub fn fast_write_text_slice(&mut self, data:&mut[u8]) -> Result<()> {
+    pub fn fast_write_text_slice(&mut self, data: &[u8]) -> Result<()> {
         self.context.fast_write_text_slice(&mut self.socket, data).check_connection_reset(&self.context.state)
     }
 
@@ -311,7 +400,7 @@ impl<'a,Stream: Read + Write> WebSocket<'a,Stream> {
 
     /// Old name for [`read`](Self::read).
     #[deprecated(note = "Use `read`")]
-    pub fn read_message(&mut self) -> Result<Message> {
+    pub fn read_message(&mut self) -> Result<Message<'_>> {
         self.read()
     }
 
@@ -338,13 +427,16 @@ pub struct WebSocketContext<'a> {
     /// The state of processing, either "active" or "closing".
     state: WebSocketState,
     /// Receive: an incomplete message being processed.
-    reuse_incomplete: IncompleteMessage,
+    reuse_incomplete: IncompleteMessage<'a>,
     /// Send in addition to regular messages E.g. "pong" or "close".
     additional_send: Option<WriteFrame>,
     /// The configuration for the websocket session.
     config: WebSocketConfig,
     /// reuse write frame
-    reuse_write_frame : FastWriteFrame
+    reuse_write_frame : FastWriteFrame<'a>,
+    /// The subprotocol negotiated during the handshake.
+    #[cfg(feature = "alloc")]
+    subprotocol: Option<String>,
 }
 
 impl<'a> WebSocketContext<'a> {
@@ -352,30 +444,88 @@ impl<'a> WebSocketContext<'a> {
     ///
     /// # Panics
     /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
+    #[cfg(feature = "alloc")]
     pub fn new(role: Role, config: Option<WebSocketConfig>) -> Self {
         Self::_new(role, FrameCodec::new(), config.unwrap_or_default())
     }
 
-    /// Create a WebSocket context that manages an post-handshake stream.
+    /// Create a WebSocket context that reads, collects and writes data messages in `buffers`
+    /// instead of allocating, and fails if they do not fit, see [`StaticBuffers`].
+    ///
+    /// Control frames, i.e. pings, pongs and close frames of at most 125 bytes, are kept in a
+    /// [`Payload`](frame::Payload), which is only allocated with the `alloc` feature. So are
+    /// messages passed to [`write`](Self::write), and the read buffer while a
+    /// [`buffer_pool`](WebSocketConfig::buffer_pool) is configured. Without `alloc` this is the
+    /// only constructor, and data messages are written with
+    /// [`fast_write_text_slice`](Self::fast_write_text_slice).
     ///
     /// # Panics
     /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
+    pub fn from_static_buffers<const READ: usize, const MESSAGE: usize, const WRITE: usize>(
+        buffers: &'a mut StaticBuffers<READ, MESSAGE, WRITE>,
+        role: Role,
+        config: Option<WebSocketConfig>,
+    ) -> Self {
+        let frame = FrameCodec::from_storage(
+            U8RingBuffer(RingBuffer::new(&mut buffers.read[..])),
+            ManagedVec::from(&mut buffers.write[..]),
+        );
+        Self::_with_storage(
+            role,
+            frame,
+            IncompleteMessage::with_storage(&mut buffers.message),
+            FastWriteFrame::with_scratch(&mut buffers.mask),
+            config.unwrap_or_default(),
+        )
+    }
+
+    /// Create a WebSocket context that manages an post-handshake stream.
+    ///
+    /// # Panics
+    /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`, or if
+    /// `part` does not fit into the read buffer.
+    #[cfg(feature = "alloc")]
     pub fn from_partially_read(part: Vec<u8>, role: Role, config: Option<WebSocketConfig>) -> Self {
         Self::_new(role, FrameCodec::from_partially_read(part), config.unwrap_or_default())
     }
 
-    fn _new(role: Role, mut frame: FrameCodec<'a>, config: WebSocketConfig) -> Self {
+    /// Create a WebSocket context that continues reading from the given buffer.
+    #[cfg(feature = "handshake")]
+    pub(crate) fn from_read_buffer(
+        buffer: crate::storage::U8RingBuffer<'a>,
+        role: Role,
+        config: Option<WebSocketConfig>,
+    ) -> Self {
+        Self::_new(role, FrameCodec::from_read_buffer(buffer), config.unwrap_or_default())
+    }
+
+    #[cfg(feature = "alloc")]
+    fn _new(role: Role, frame: FrameCodec<'a>, config: WebSocketConfig) -> Self {
+        Self::_with_storage(role, frame, IncompleteMessage::new(), FastWriteFrame::new(), config)
+    }
+
+    fn _with_storage(
+        role: Role,
+        mut frame: FrameCodec<'a>,
+        reuse_incomplete: IncompleteMessage<'a>,
+        reuse_write_frame: FastWriteFrame<'a>,
+        config: WebSocketConfig,
+    ) -> Self {
         config.assert_valid();
         frame.set_max_out_buffer_len(config.max_write_buffer_size);
         frame.set_out_buffer_write_len(config.write_buffer_size);
+        #[cfg(feature = "std")]
+        frame.set_buffer_pool(config.buffer_pool);
         Self {
             role,
             frame,
             state: WebSocketState::Active,
-            reuse_incomplete: IncompleteMessage::new(),
+            reuse_incomplete,
             additional_send: None,
             config,
-            reuse_write_frame: FastWriteFrame::new(),
+            reuse_write_frame,
+            #[cfg(feature = "alloc")]
+            subprotocol: None,
         }
     }
 
@@ -388,6 +538,8 @@ impl<'a> WebSocketContext<'a> {
         self.config.assert_valid();
         self.frame.set_max_out_buffer_len(self.config.max_write_buffer_size);
         self.frame.set_out_buffer_write_len(self.config.write_buffer_size);
+        #[cfg(feature = "std")]
+        self.frame.set_buffer_pool(self.config.buffer_pool);
     }
 
     /// Read the configuration.
@@ -395,6 +547,20 @@ impl<'a> WebSocketContext<'a> {
         &self.config
     }
 
+    /// The subprotocol negotiated during the handshake, if any.
+    #[cfg(feature = "alloc")]
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.subprotocol.as_deref()
+    }
+
+    /// Set the subprotocol negotiated during the handshake.
+    ///
+    /// Only needed when the handshake was performed outside of this crate.
+    #[cfg(feature = "alloc")]
+    pub fn set_subprotocol(&mut self, subprotocol: Option<String>) {
+        self.subprotocol = subprotocol;
+    }
+
     /// Check if it is possible to read messages.
     ///
     /// Reading is impossible after receiving `Message::Close`. It is still possible after
@@ -414,13 +580,20 @@ impl<'a> WebSocketContext<'a> {
     ///
     /// This function sends pong and close responses automatically.
     /// However, it never blocks on write.
-    pub fn read<Stream>(&mut self, stream: &mut Stream) -> Result<Message>
+    pub fn read<Stream>(&mut self, stream: &mut Stream) -> Result<Message<'_>>
     where
         Stream: Read + Write,
     {
         // Do not read from already closed connections.
         self.state.check_not_terminated()?;
 
+        // The previous message is done with, so a pooled connection with nothing buffered
+        // does not keep buffers as large as the largest message it received.
+        #[cfg(feature = "std")]
+        if self.frame.release_idle_buffers() {
+            self.reuse_incomplete.release();
+        }
+
         loop {
             if self.additional_send.is_some() {
                 // Since we may get ping or close, we need to reply to the messages even during read.
@@ -433,10 +606,13 @@ impl<'a> WebSocketContext<'a> {
 
             // If we get here, either write blocks or we have nothing to write.
             // Thus if read blocks, just let it return WouldBlock.
-            if let Some(message) = self.read_message_frame(stream)? {
-                trace!("Received message {}", message);
-                return Ok(message);
-            }
+            let message = match self.read_message_frame(stream)? {
+                FrameResult::Nothing => continue,
+                FrameResult::Control(message) => message,
+                FrameResult::Data => self.reuse_incomplete.complete()?,
+            };
+            trace!("Received message {}", message);
+            return Ok(message);
         }
     }
 
@@ -463,7 +639,9 @@ impl<'a> WebSocketContext<'a> {
         }
 
         let frame = match message {
+            #[cfg(feature = "alloc")]
             Message::Text(data) => WriteFrame::message(data.into(), OpCode::Data(OpData::Text), true),
+            #[cfg(feature = "alloc")]
             Message::Binary(data) => WriteFrame::message(data, OpCode::Data(OpData::Binary), true),
             Message::Ping(data) => WriteFrame::ping(data),
             Message::Pong(data) => {
@@ -486,25 +664,14 @@ impl<'a> WebSocketContext<'a> {
     }
 
     // only write 
+    #[cfg(feature = "alloc")]
     pub fn fast_write<Stream>(&mut self, stream: &mut Stream, message: Message) -> Result<()>
     where
         Stream: Read + Write,
     {
-        // When terminated, return AlreadyClosed.
-        self.state.check_not_terminated()?;
-
-        // Do not write after sending a close frame.
-        if !self.state.is_active() {
-            return Err(Error::Protocol(ProtocolError::SendAfterClosing));
-        }
-
-        match message {
-            Message::Text(data) => {
-                self.reuse_write_frame.reuse(&mut data.into_bytes(), OpCode::Data(OpData::Text), true);
-            },
-            Message::Binary(mut data) => {
-                self.reuse_write_frame.reuse(&mut data, OpCode::Data(OpData::Binary), true);
-            }
+        let (data, opcode) = match message {
+            Message::Text(data) => (data.into_bytes(), OpData::Text),
+            Message::Binary(data) => (data, OpData::Binary),
             Message::Ping(_) => todo!(),
             Message::Pong(_) => todo!(),
             Message::Close(_) => todo!(),
@@ -513,45 +680,48 @@ impl<'a> WebSocketContext<'a> {
             Message::ReadBinary(_) => todo!(),
             Message::ReadFrame(_) => todo!(),
         };
-
-        match self.role {
-            Role::Server => {}
-            Role::Client => {
-                // 5.  If the data is being sent by the client, the frame(s) MUST be
-                // masked as defined in Section 5.3. (RFC 6455)
-                self.reuse_write_frame.set_random_mask();
-            }
-        }
-
-        self.reuse_write_frame.format(stream)?;
-        Ok(())
+        self.fast_write_data(stream, &data, opcode)
     }
 
     // only write 
-    pub fn fast_write_text_slice<Stream>(&mut self, stream: &mut Stream, data:&mut [u8]) -> Result<()>
+    pub fn fast_write_text_slice<Stream>(&mut self, stream: &mut Stream, data: &[u8]) -> Result<()>
     where
     Stream: Read + Write,
+    {
+        self.fast_write_data(stream, data, OpData::Text)
+    }
+
+    /// Write a single data frame straight to the stream, `data` is not modified.
+    fn fast_write_data<Stream>(
+        &mut self,
+        stream: &mut Stream,
+        data: &[u8],
+        opcode: OpData,
+    ) -> Result<()>
+    where
+        Stream: Read + Write,
     {
         // When terminated, return AlreadyClosed.
         self.state.check_not_terminated()?;
 
         // Do not write after sending a close frame.
         if !self.state.is_active() {
-        return Err(Error::Protocol(ProtocolError::SendAfterClosing));
+            return Err(Error::Protocol(ProtocolError::SendAfterClosing));
         }
 
-        self.reuse_write_frame.reuse(data, OpCode::Data(OpData::Text), true);
+        self.reuse_write_frame.reuse(OpCode::Data(opcode), true);
 
         match self.role {
             Role::Server => {}
             Role::Client => {
                 // 5.  If the data is being sent by the client, the frame(s) MUST be
                 // masked as defined in Section 5.3. (RFC 6455)
-                self.reuse_write_frame.set_random_mask();
+                let generator = self.mask_generator()?;
+                self.reuse_write_frame.set_random_mask(generator);
             }
         }
 
-        self.reuse_write_frame.format(stream)?;
+        self.reuse_write_frame.format(data, stream)?;
         Ok(())
     }
 
@@ -644,11 +814,11 @@ impl<'a> WebSocketContext<'a> {
     }
 
     /// Try to decode one message frame. May return None.
-    fn read_message_frame<Stream>(&mut self, stream: &mut Stream) -> Result<Option<Message>>
+    fn read_message_frame<Stream>(&mut self, stream: &mut Stream) -> Result<FrameResult>
     where
         Stream: Read + Write,
     {
-        if let Some(frame) = self
+        if let Some(mut frame) = self
             .frame
             .read_frame(stream)
             .check_connection_reset(&self.state)?
@@ -703,20 +873,26 @@ impl<'a> WebSocketContext<'a> {
                         }
                         OpCtl::Close =>{
                             let a = frame.into_close()?;
-                            Ok(self.do_close(a).map(Message::Close))
+                            Ok(self
+                                .do_close(a)
+                                .map_or(FrameResult::Nothing, |close| {
+                                    FrameResult::Control(Message::Close(close))
+                                }))
                         } 
                         OpCtl::Reserved(i) => {
                             Err(Error::Protocol(ProtocolError::UnknownControlFrameType(i)))
                         }
                         OpCtl::Ping => {
-                            let data = frame.into_data();
+                            let data = frame.control_payload();
                             // No ping processing after we sent a close frame.
                             if self.state.is_active() {
                                 self.set_additional(WriteFrame::pong(data.clone()));
                             }
-                            Ok(Some(Message::Ping(data)))
+                            Ok(FrameResult::Control(Message::Ping(data)))
+                        }
+                        OpCtl::Pong => {
+                            Ok(FrameResult::Control(Message::Pong(frame.control_payload())))
                         }
-                        OpCtl::Pong => Ok(Some(Message::Pong(frame.into_data()))),
                     }
                 }
 
@@ -724,32 +900,28 @@ impl<'a> WebSocketContext<'a> {
                     let fin = frame.header().is_final;
                     match data {
                         OpData::Continue => {
-                            self.reuse_incomplete.extend(frame)?;
+                            self.reuse_incomplete.extend(&mut frame)?;
                             
                             if fin {
-                                Ok(Some(self.reuse_incomplete.complete()?))
+                                Ok(FrameResult::Data)
                             } else {
-                                Ok(None)
+                                Ok(FrameResult::Nothing)
                             }
                         }
                         // c if self.incomplete.is_some() => {
                         //     Err(Error::Protocol(ProtocolError::ExpectedFragment(c)))
                         // }
                         OpData::Text | OpData::Binary => {
-                            let msg = {
-                                let message_type = match data {
-                                    OpData::Text => IncompleteMessageType::Text,
-                                    OpData::Binary => IncompleteMessageType::Binary,
-                                    _ => panic!("Bug: message is not text nor binary"),
-                                };
-                                let m = self.reuse_incomplete.reuse(message_type);
-                                m.extend(frame)?;
-                                m
+                            let message_type = match data {
+                                OpData::Text => IncompleteMessageType::Text,
+                                OpData::Binary => IncompleteMessageType::Binary,
+                                _ => panic!("Bug: message is not text nor binary"),
                             };
+                            self.reuse_incomplete.reuse(message_type).extend(&mut frame)?;
                             if fin {
-                                return Ok(Some(msg.complete()?));
+                                return Ok(FrameResult::Data);
                             }
-                            Ok(None)
+                            Ok(FrameResult::Nothing)
                         }
                         OpData::Reserved(i) => {
                             Err(Error::Protocol(ProtocolError::UnknownDataFrameType(i)))
@@ -816,7 +988,7 @@ impl<'a> WebSocketContext<'a> {
             Role::Client => {
                 // 5.  If the data is being sent by the client, the frame(s) MUST be
                 // masked as defined in Section 5.3. (RFC 6455)
-                frame.set_random_mask();
+                frame.set_random_mask(self.mask_generator()?);
             }
         }
 
@@ -824,6 +996,11 @@ impl<'a> WebSocketContext<'a> {
         self.frame.buffer_frame(stream, frame).check_connection_reset(&self.state)
     }
 
+    /// The generator to mask the frames of a client with.
+    fn mask_generator(&self) -> Result<&'static dyn MaskGenerator> {
+        self.config.mask_generator.ok_or(Error::Protocol(ProtocolError::MissingMaskGenerator))
+    }
+
     /// Replace `additional_send` if it is currently a `Pong` message.
     fn set_additional(&mut self, add: WriteFrame) {
         let empty_or_pong = self
@@ -836,6 +1013,16 @@ impl<'a> WebSocketContext<'a> {
     }
 }
 
+/// What reading a single frame gave.
+enum FrameResult {
+    /// No message yet, e.g. a data frame that does not end its message.
+    Nothing,
+    /// A control message.
+    Control(Message<'static>),
+    /// The data message collected in `reuse_incomplete` is complete.
+    Data,
+}
+
 /// The current connection state.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum WebSocketState {
@@ -909,13 +1096,37 @@ impl<T> CheckConnectionReset for Result<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Message, Role, WebSocket, WebSocketConfig};
-    use crate::error::{CapacityError, Error};
+    use super::{frame::FixedMask, Message, Role, WebSocket, WebSocketConfig, WebSocketContext};
+    use crate::{
+        error::{CapacityError, Error, ProtocolError},
+        storage::{BufferPool, StaticBuffers},
+    };
 
     use std::{io, io::Cursor};
 
     struct WriteMoc<Stream>(Stream);
 
+    /// Reads the data it is given, then blocks.
+    struct Blocking(Cursor<Vec<u8>>);
+
+    impl io::Read for Blocking {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                size => Ok(size),
+            }
+        }
+    }
+
+    impl io::Write for Blocking {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
     impl<Stream> io::Write for WriteMoc<Stream> {
         fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
             Ok(buf.len())
@@ -950,17 +1161,219 @@ mod tests {
             assert_eq!(pong,vec![3])
         }
         if let Message::ReadText(text) = socket.read().unwrap(){
-            unsafe{
-                if let Ok(text) = (*text).into_string(){
-                    assert_eq!(text,"Hello, World!")
-                }
+            if let Ok(text) = text.into_string(){
+                assert_eq!(text,"Hello, World!")
             }
         }
         if let Message::ReadBinary(binary) = socket.read().unwrap(){
-            unsafe{
-                assert_eq!((*binary).into_vec(),vec![0x01, 0x02, 0x03])
+            assert_eq!(binary.into_vec(),vec![0x01, 0x02, 0x03])
+        }
+    }
+
+    #[test]
+    fn receive_masked_messages() {
+        // A ping from a client, masked with [0x10, 0x20, 0x30, 0x40].
+        let incoming = Cursor::new(vec![0x89, 0x82, 0x10, 0x20, 0x30, 0x40, 0x11, 0x22]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Server, None);
+        match socket.read().unwrap() {
+            Message::Ping(ping) => assert_eq!(ping, vec![1, 2]),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fast_write_masks_copy() {
+        // Longer than a masking chunk, the data must arrive intact and stay untouched.
+        let data: Vec<u8> = (0..10_000).map(|i| b'a' + (i % 26) as u8).collect();
+        let original = data.clone();
+        let mut socket = WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Client, None);
+        socket.fast_write_text_slice(&data).unwrap();
+        assert_eq!(data, original);
+
+        let out = socket.get_ref().get_ref();
+        assert_eq!(out[..4], [0x81, 0xfe, 0x27, 0x10]);
+        let mask = [out[4], out[5], out[6], out[7]];
+        let payload: Vec<u8> = out[8..].iter().enumerate().map(|(i, b)| b ^ mask[i & 3]).collect();
+        assert_eq!(payload, data);
+    }
+
+    #[test]
+    fn fixed_mask_output() {
+        static MASK: FixedMask = FixedMask([0x01, 0x02, 0x03, 0x04]);
+        let config =
+            WebSocketConfig { mask_generator: Some(&MASK), ..WebSocketConfig::default() };
+        let mut socket =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Client, Some(config));
+        socket.send(Message::Binary(vec![0x10, 0x20, 0x30, 0x40, 0x50])).unwrap();
+        socket.fast_write_text_slice(b"Hi").unwrap();
+
+        assert_eq!(
+            socket.get_ref().get_ref().as_slice(),
+            [
+                0x82, 0x85, 0x01, 0x02, 0x03, 0x04, 0x11, 0x22, 0x33, 0x44, 0x51, // binary
+                0x81, 0x82, 0x01, 0x02, 0x03, 0x04, 0x49, 0x6b, // text
+            ]
+        );
+    }
+
+    #[test]
+    fn static_buffers_receive() {
+        let mut buffers = StaticBuffers::<64, 16, 32>::new();
+        let mut context = WebSocketContext::from_static_buffers(&mut buffers, Role::Client, None);
+        let mut stream = WriteMoc(Cursor::new(vec![
+            0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x89, 0x02, 0x01, 0x02, 0x80,
+            0x06, 0x57, 0x6f, 0x72, 0x6c, 0x64, 0x21, 0x82, 0x03, 0x01, 0x02, 0x03,
+        ]));
+
+        match context.read(&mut stream).unwrap() {
+            Message::Ping(ping) => assert_eq!(ping, vec![1, 2]),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        match context.read(&mut stream).unwrap() {
+            Message::ReadText(text) => assert_eq!(text.into_string().unwrap(), "Hello, World!"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        match context.read(&mut stream).unwrap() {
+            Message::ReadBinary(binary) => assert_eq!(binary.into_vec(), [1, 2, 3]),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn static_buffers_are_bounded() {
+        // The message does not fit.
+        let mut buffers = StaticBuffers::<64, 10, 32>::new();
+        let mut context = WebSocketContext::from_static_buffers(&mut buffers, Role::Client, None);
+        let mut stream = WriteMoc(Cursor::new(vec![
+            0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x80, 0x06, 0x57, 0x6f, 0x72,
+            0x6c, 0x64, 0x21,
+        ]));
+        assert!(matches!(
+            context.read(&mut stream),
+            Err(Error::Capacity(CapacityError::MessageTooLong { size: 13, max_size: 10 }))
+        ));
+
+        // The frame does not fit into the read buffer.
+        let mut buffers = StaticBuffers::<4, 16, 32>::new();
+        let mut context = WebSocketContext::from_static_buffers(&mut buffers, Role::Client, None);
+        let mut stream = WriteMoc(Cursor::new(vec![0x82, 0x05, 1, 2, 3, 4, 5]));
+        assert!(matches!(
+            context.read(&mut stream),
+            Err(Error::Capacity(CapacityError::MessageTooLong { size: 5, max_size: 4 }))
+        ));
+
+        // The queued frame does not fit.
+        let mut buffers = StaticBuffers::<64, 16, 4>::new();
+        let mut context = WebSocketContext::from_static_buffers(&mut buffers, Role::Server, None);
+        let mut stream = Cursor::new(Vec::new());
+        assert!(matches!(
+            context.write(&mut stream, Message::Binary(vec![0; 10])),
+            Err(Error::WriteBufferFull(_))
+        ));
+    }
+
+    #[test]
+    fn static_buffers_keep_pong_while_blocked() {
+        /// Reads the data it is given, writes only while `writable`.
+        struct Stalling {
+            incoming: Cursor<Vec<u8>>,
+            written: Vec<u8>,
+            writable: bool,
+        }
+
+        impl io::Read for Stalling {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.incoming.read(buf)
+            }
+        }
+
+        impl io::Write for Stalling {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if !self.writable {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
             }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut buffers = StaticBuffers::<64, 16, 16>::new();
+        let mut context = WebSocketContext::from_static_buffers(&mut buffers, Role::Server, None);
+        let incoming = Cursor::new(vec![0x89, 0x82, 0, 0, 0, 0, 1, 2]);
+        let mut stream = Stalling { incoming, written: Vec::new(), writable: false };
+
+        // 14 of 16 bytes are queued, the pong does not fit until they are written.
+        context.write(&mut stream, Message::Binary(vec![0; 12])).unwrap();
+        assert!(context.read(&mut stream).unwrap().is_ping());
+        let blocked = context.flush(&mut stream);
+        assert!(matches!(blocked, Err(Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock));
+
+        stream.writable = true;
+        context.flush(&mut stream).unwrap();
+        assert_eq!(stream.written[14..], [0x8a, 0x02, 1, 2]);
+    }
+
+    #[test]
+    fn static_buffers_fast_write_masks_in_chunks() {
+        let data: Vec<u8> = (0..1000).map(|i| b'a' + (i % 26) as u8).collect();
+        let mut buffers = StaticBuffers::<64, 16, 32>::new();
+        let mut context = WebSocketContext::from_static_buffers(&mut buffers, Role::Client, None);
+        let mut stream = Cursor::new(Vec::new());
+        context.fast_write_text_slice(&mut stream, &data).unwrap();
+
+        let out = stream.get_ref();
+        assert_eq!(out[..4], [0x81, 0xfe, 0x03, 0xe8]);
+        let mask = [out[4], out[5], out[6], out[7]];
+        let payload: Vec<u8> = out[8..].iter().enumerate().map(|(i, b)| b ^ mask[i & 3]).collect();
+        assert_eq!(payload, data);
+    }
+
+    #[test]
+    fn pooled_connection_frees_buffers_when_idle() {
+        // A binary message in two fragments of 20000 bytes.
+        let mut incoming = vec![0x02, 0x7e, 0x4e, 0x20];
+        incoming.extend_from_slice(&[1; 20000]);
+        incoming.extend_from_slice(&[0x80, 0x7e, 0x4e, 0x20]);
+        incoming.extend_from_slice(&[2; 20000]);
+
+        let pool: &'static BufferPool = Box::leak(Box::new(BufferPool::new(32 * 1024)));
+        let config = WebSocketConfig { buffer_pool: Some(pool), ..WebSocketConfig::default() };
+        let stream = Blocking(Cursor::new(incoming));
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Client, Some(config));
+        match socket.read().unwrap() {
+            Message::ReadBinary(binary) => assert_eq!(binary.len(), 40000),
+            other => panic!("unexpected message: {:?}", other),
         }
+        assert!(socket.context.reuse_incomplete.capacity() >= 40000);
+
+        // Waiting for the next message, the connection holds no buffer at all.
+        let blocked = socket.read();
+        assert!(matches!(blocked, Err(Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock));
+        assert_eq!(socket.context.reuse_incomplete.capacity(), 0);
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn missing_mask_generator() {
+        let config = WebSocketConfig { mask_generator: None, ..WebSocketConfig::default() };
+        let mut client =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Client, Some(config));
+        assert!(matches!(
+            client.send(Message::Binary(vec![1])),
+            Err(Error::Protocol(ProtocolError::MissingMaskGenerator))
+        ));
+        assert!(matches!(
+            client.fast_write_text_slice(b"Hi"),
+            Err(Error::Protocol(ProtocolError::MissingMaskGenerator))
+        ));
+
+        // Servers never mask.
+        let mut server =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, Some(config));
+        server.send(Message::Binary(vec![1])).unwrap();
     }
 
     #[test]
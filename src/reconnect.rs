@@ -0,0 +1,431 @@
+//! A blocking client that transparently re-dials the server when the connection is lost.
+
+use std::{net::TcpStream, thread, time::Duration};
+
+use http::HeaderValue;
+use log::*;
+use rand::Rng;
+
+use crate::{
+    client::{connect_with_config, IntoClientRequest},
+    error::{Error, ProtocolError, Result, UrlError},
+    handshake::client::{generate_key, Request},
+    protocol::{
+        frame::{coding::CloseCode, CloseFrame},
+        Message, WebSocket, WebSocketConfig,
+    },
+    stream::MaybeTlsStream,
+};
+
+/// The WebSocket type managed by [`ReconnectingClient`].
+pub type ClientSocket = WebSocket<'static, MaybeTlsStream<TcpStream>>;
+
+/// Backoff settings of a [`ReconnectingClient`].
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectConfig {
+    /// The delay before the first reconnection attempt.
+    /// The default value is 100 milliseconds.
+    pub initial_backoff: Duration,
+    /// The upper limit of the delay between two attempts.
+    /// The default value is 30 seconds.
+    pub max_backoff: Duration,
+    /// The factor the delay grows with after each failed attempt.
+    /// The default value is 2.
+    pub multiplier: u32,
+    /// The fraction of the delay that is randomized, between 0 and 1, so that many clients
+    /// disconnected at once don't reconnect in lockstep. The default value is 0.5, i.e. the
+    /// actual delay is between 50% and 100% of the computed one.
+    pub jitter: f64,
+    /// The number of consecutive failed attempts after which the last error is returned.
+    /// The default value is `None`, i.e. retry forever.
+    pub max_attempts: Option<u32>,
+    /// The maximum number of HTTP redirects to follow during each handshake.
+    /// The default value is 3.
+    pub max_redirects: u8,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2,
+            jitter: 0.5,
+            max_attempts: None,
+            max_redirects: 3,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// The delay before the given attempt (starting at 0), without jitter.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.checked_pow(attempt).unwrap_or(u32::MAX);
+        let delay = self.initial_backoff.checked_mul(factor).unwrap_or(self.max_backoff);
+        delay.min(self.max_backoff)
+    }
+
+    fn jittered(&self, delay: Duration) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Why the connection of a [`ReconnectingClient`] was lost or could not be established.
+#[derive(Debug, Clone, Copy)]
+pub enum Disconnect<'e> {
+    /// The connection has been closed with a close handshake, contains the close frame
+    /// sent by the server, if any.
+    Closed(Option<&'e CloseFrame<'static>>),
+    /// Connecting, reading or writing failed with the given error.
+    Error(&'e Error),
+}
+
+/// Decides whether a [`ReconnectingClient`] should reconnect or give up.
+///
+/// It is implemented for closures `FnMut(&Disconnect) -> bool`.
+pub trait ReconnectPolicy {
+    /// Returns `true` if the client should reconnect after `reason`, `false` if the
+    /// disconnect is terminal and should be reported to the caller.
+    fn should_reconnect(&mut self, reason: &Disconnect<'_>) -> bool;
+}
+
+impl<F> ReconnectPolicy for F
+where
+    F: FnMut(&Disconnect<'_>) -> bool,
+{
+    fn should_reconnect(&mut self, reason: &Disconnect<'_>) -> bool {
+        self(reason)
+    }
+}
+
+/// The default policy of a [`ReconnectingClient`].
+///
+/// Reconnects after connection resets, I/O errors and normal closes. A close with
+/// [`CloseCode::Policy`], an HTTP 4xx response to the handshake and all other errors
+/// (e.g. protocol violations or invalid URLs) are terminal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultReconnectPolicy;
+
+impl ReconnectPolicy for DefaultReconnectPolicy {
+    fn should_reconnect(&mut self, reason: &Disconnect<'_>) -> bool {
+        match reason {
+            Disconnect::Closed(Some(frame)) => frame.code != CloseCode::Policy,
+            Disconnect::Closed(None) => true,
+            Disconnect::Error(error) => match error {
+                Error::ConnectionClosed | Error::AlreadyClosed | Error::Io(_) => true,
+                Error::Protocol(ProtocolError::ResetWithoutClosingHandshake) => true,
+                Error::Url(UrlError::UnableToConnect(_)) => true,
+                Error::Http(response) => !response.status().is_client_error(),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// A blocking WebSocket client that re-dials the server with exponential backoff and
+/// jitter when the connection is lost.
+///
+/// After each successful connection the `on_connect` hook is run, e.g. to resubscribe to
+/// the streams the application is interested in. Each connection gets a new
+/// [generation](ReconnectingClient::generation) number, so that the application can tell
+/// which messages were received over which connection.
+///
+/// ```no_run
+/// use tungstenite::{Message, ReconnectingClient};
+///
+/// let mut client = ReconnectingClient::connect("ws://localhost:3012/socket", |socket| {
+///     socket.send(Message::Text(r#"{"subscribe":"trades"}"#.into()))
+/// })
+/// .unwrap();
+/// loop {
+///     let msg = client.read().unwrap();
+///     println!("Received {:?} on connection {}", msg, client.generation());
+/// }
+/// ```
+pub struct ReconnectingClient<F, P = DefaultReconnectPolicy> {
+    request: Request,
+    config: Option<WebSocketConfig>,
+    reconnect: ReconnectConfig,
+    policy: P,
+    on_connect: F,
+    socket: Option<ClientSocket>,
+    close_frame: Option<CloseFrame<'static>>,
+    generation: u64,
+}
+
+impl<F, P> std::fmt::Debug for ReconnectingClient<F, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectingClient")
+            .field("uri", self.request.uri())
+            .field("reconnect", &self.reconnect)
+            .field("connected", &self.socket.is_some())
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl<F> ReconnectingClient<F>
+where
+    F: FnMut(&mut ClientSocket) -> Result<()>,
+{
+    /// Connect to the given WebSocket with the default configuration and policy.
+    pub fn connect<Req: IntoClientRequest>(request: Req, on_connect: F) -> Result<Self> {
+        Self::connect_with_config(
+            request,
+            None,
+            ReconnectConfig::default(),
+            DefaultReconnectPolicy,
+            on_connect,
+        )
+    }
+}
+
+impl<F, P> ReconnectingClient<F, P>
+where
+    F: FnMut(&mut ClientSocket) -> Result<()>,
+    P: ReconnectPolicy,
+{
+    /// Connect to the given WebSocket, using the given websocket configuration for every
+    /// connection and the given backoff settings and policy for reconnecting.
+    ///
+    /// The initial connection is retried according to the policy as well.
+    pub fn connect_with_config<Req: IntoClientRequest>(
+        request: Req,
+        config: Option<WebSocketConfig>,
+        reconnect: ReconnectConfig,
+        policy: P,
+        on_connect: F,
+    ) -> Result<Self> {
+        let mut client = ReconnectingClient {
+            request: request.into_client_request()?,
+            config,
+            reconnect,
+            policy,
+            on_connect,
+            socket: None,
+            close_frame: None,
+            generation: 0,
+        };
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    /// The number of the current connection, starting at 1 and incremented on every
+    /// successful reconnect.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns `true` if the client is currently connected.
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Returns a shared reference to the current connection, if any.
+    pub fn get_ref(&self) -> Option<&ClientSocket> {
+        self.socket.as_ref()
+    }
+
+    /// Returns a mutable reference to the current connection, if any.
+    pub fn get_mut(&mut self) -> Option<&mut ClientSocket> {
+        self.socket.as_mut()
+    }
+
+    /// Read a message, reconnecting as long as the policy allows it.
+    ///
+    /// Close frames are returned like with [`WebSocket::read`], the reconnect happens on the
+    /// following call. Errors that leave the connection usable, e.g. a message that is not
+    /// valid UTF-8, are returned without reconnecting.
+    ///
+    /// The connection may be replaced by the next call, so data messages are returned with
+    /// their data copied, see [`Message::into_owned`].
+    pub fn read(&mut self) -> Result<Message<'static>> {
+        loop {
+            let socket = self.connected()?;
+            match socket.read() {
+                Ok(Message::Close(frame)) => {
+                    self.close_frame = frame.clone();
+                    return Ok(Message::Close(frame));
+                }
+                Ok(message) => return message.into_owned(),
+                Err(error) if connection_lost(&error) => self.disconnected(error)?,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Send a message over the current connection, reconnecting first if needed.
+    ///
+    /// If the connection is lost while sending, the error is returned, as the message may
+    /// not have been delivered, and the client reconnects on the next call. Other errors, e.g.
+    /// [`Error::WriteBufferFull`], leave the connection as it is.
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        let result = self.connected()?.send(message);
+        if let Err(error) = &result {
+            if connection_lost(error) {
+                self.socket = None;
+                self.close_frame = None;
+            }
+        }
+        result
+    }
+
+    /// Close the current connection without reconnecting afterwards.
+    pub fn close(mut self, code: Option<CloseFrame>) -> Result<()> {
+        match self.socket.take() {
+            Some(mut socket) => {
+                socket.close(code)?;
+                // Drive the close handshake until the server closes the connection.
+                loop {
+                    match socket.read() {
+                        Ok(_) => continue,
+                        Err(Error::ConnectionClosed) => return Ok(()),
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn connected(&mut self) -> Result<&mut ClientSocket> {
+        if self.socket.is_none() {
+            self.reconnect()?;
+        }
+        Ok(self.socket.as_mut().expect("Bug: not connected after reconnect"))
+    }
+
+    /// Handle a lost connection: returns the error if it is terminal.
+    fn disconnected(&mut self, error: Error) -> Result<()> {
+        self.socket = None;
+        let close_frame = self.close_frame.take();
+        let reconnect = match (&error, &close_frame) {
+            (Error::ConnectionClosed, _) => {
+                self.policy.should_reconnect(&Disconnect::Closed(close_frame.as_ref()))
+            }
+            _ => self.policy.should_reconnect(&Disconnect::Error(&error)),
+        };
+        if reconnect {
+            debug!("Connection lost, reconnecting: {}", error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.try_connect() {
+                Ok(socket) => {
+                    self.socket = Some(socket);
+                    return Ok(());
+                }
+                Err(error) => {
+                    if !self.policy.should_reconnect(&Disconnect::Error(&error))
+                        || self.reconnect.max_attempts.is_some_and(|max| attempt + 1 >= max)
+                    {
+                        return Err(error);
+                    }
+                    let delay = self.reconnect.jittered(self.reconnect.backoff(attempt));
+                    debug!("Connection attempt {} failed: {}, retry in {:?}", attempt, error, delay);
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn try_connect(&mut self) -> Result<ClientSocket> {
+        let mut builder = Request::builder()
+            .uri(self.request.uri().clone())
+            .method(self.request.method().clone())
+            .version(self.request.version());
+        let headers = builder.headers_mut().expect("Failed to create `Request`");
+        *headers = self.request.headers().clone();
+        // A fresh key for every handshake.
+        headers.insert("Sec-WebSocket-Key", HeaderValue::from_str(&generate_key())?);
+        let request = builder.body(())?;
+
+        let (mut socket, _) =
+            connect_with_config(request, self.config, self.reconnect.max_redirects)?;
+        (self.on_connect)(&mut socket)?;
+        self.generation += 1;
+        self.close_frame = None;
+        debug!("Connected to {} (generation {})", self.request.uri(), self.generation);
+        Ok(socket)
+    }
+}
+
+/// Whether `error` means that the connection is unusable, as opposed to e.g. a message that is
+/// too long or not valid UTF-8.
+fn connection_lost(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::ConnectionClosed
+            | Error::AlreadyClosed
+            | Error::Io(_)
+            | Error::Tls(_)
+            | Error::Protocol(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        connection_lost, DefaultReconnectPolicy, Disconnect, ReconnectConfig, ReconnectPolicy,
+    };
+    use crate::{
+        error::{CapacityError, Error, ProtocolError},
+        protocol::frame::{coding::CloseCode, CloseFrame},
+    };
+
+    #[test]
+    fn backoff() {
+        let config = ReconnectConfig { max_backoff: Duration::from_secs(1), ..Default::default() };
+        let delays: Vec<_> = (0..5).map(|attempt| config.backoff(attempt).as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000]);
+        assert_eq!(config.backoff(100), Duration::from_secs(1));
+
+        for _ in 0..100 {
+            let delay = config.jittered(Duration::from_millis(800));
+            assert!(delay >= Duration::from_millis(400) && delay <= Duration::from_millis(800));
+        }
+        let config = ReconnectConfig { jitter: 0.0, ..config };
+        assert_eq!(config.jittered(Duration::from_millis(800)), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn default_policy() {
+        let mut policy = DefaultReconnectPolicy;
+        let reset = Error::Protocol(ProtocolError::ResetWithoutClosingHandshake);
+        assert!(policy.should_reconnect(&Disconnect::Error(&reset)));
+        assert!(policy.should_reconnect(&Disconnect::Error(&Error::ConnectionClosed)));
+        assert!(policy.should_reconnect(&Disconnect::Closed(None)));
+
+        let away = CloseFrame { code: CloseCode::Away, reason: "restart".into() };
+        assert!(policy.should_reconnect(&Disconnect::Closed(Some(&away))));
+        let violation = CloseFrame { code: CloseCode::Policy, reason: "banned".into() };
+        assert!(!policy.should_reconnect(&Disconnect::Closed(Some(&violation))));
+
+        let forbidden = http::Response::builder().status(403).body(None).unwrap();
+        assert!(!policy.should_reconnect(&Disconnect::Error(&Error::Http(forbidden))));
+        let unavailable = http::Response::builder().status(503).body(None).unwrap();
+        assert!(policy.should_reconnect(&Disconnect::Error(&Error::Http(unavailable))));
+        assert!(!policy.should_reconnect(&Disconnect::Error(&Error::Utf8)));
+    }
+
+    #[test]
+    fn lost_connections() {
+        assert!(connection_lost(&Error::ConnectionClosed));
+        assert!(connection_lost(&Error::Io(std::io::ErrorKind::ConnectionReset.into())));
+        assert!(connection_lost(&Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)));
+        assert!(!connection_lost(&Error::Utf8));
+        let too_long = CapacityError::MessageTooLong { size: 2, max_size: 1 };
+        assert!(!connection_lost(&Error::Capacity(too_long)));
+        assert!(!connection_lost(&Error::WriteBufferFull(crate::Message::Text("a".into()))));
+    }
+}
@@ -3,13 +3,17 @@
 pub use crate::handshake::server::ServerHandshake;
 
 use crate::handshake::{
-    server::{Callback, NoCallback},
+    server::{NoCallback, StreamCallback},
     HandshakeError,
 };
+#[cfg(unix)]
+use crate::Error;
 
 use crate::protocol::{WebSocket, WebSocketConfig};
 
 use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
 
 /// Accept the given Stream as a WebSocket.
 ///
@@ -47,7 +51,7 @@ pub fn accept<'a,S: Read + Write>(
 /// This function does the same as `accept()` but accepts an extra callback
 /// for header processing. The callback receives headers of the incoming
 /// requests and is able to add extra headers to the reply.
-pub fn accept_hdr_with_config<'a,S: Read + Write, C: Callback>(
+pub fn accept_hdr_with_config<'a,S: Read + Write, C: StreamCallback<S>>(
     stream: S,
     callback: C,
     config: Option<WebSocketConfig>,
@@ -59,10 +63,36 @@ pub fn accept_hdr_with_config<'a,S: Read + Write, C: Callback>(
 ///
 /// This function does the same as `accept()` but accepts an extra callback
 /// for header processing. The callback receives headers of the incoming
-/// requests and is able to add extra headers to the reply.
-pub fn accept_hdr<'a,S: Read + Write, C: Callback>(
+/// requests and is able to add extra headers to the reply. A [`StreamCallback`] also
+/// receives the stream and may attach data to the WebSocket.
+pub fn accept_hdr<'a,S: Read + Write, C: StreamCallback<S>>(
     stream: S,
     callback: C,
 ) -> Result<WebSocket<'a,S>, HandshakeError<ServerHandshake<S, C>>> {
     accept_hdr_with_config(stream, callback, None)
 }
+
+/// Accept the next connection on the given Unix domain socket listener as a WebSocket.
+///
+/// This function blocks until a client connects and the handshake has completed. Since Unix
+/// domain sockets are only reachable from the same host, no TLS is involved.
+#[cfg(unix)]
+pub fn accept_unix<'a>(
+    listener: &UnixListener,
+) -> Result<WebSocket<'a, UnixStream>, HandshakeError<ServerHandshake<UnixStream, NoCallback>>> {
+    accept_unix_hdr_with_config(listener, NoCallback, None)
+}
+
+/// Accept the next connection on the given Unix domain socket listener as a WebSocket.
+///
+/// This function does the same as `accept_unix()` but accepts an extra callback for header
+/// processing and a configuration.
+#[cfg(unix)]
+pub fn accept_unix_hdr_with_config<'a, C: StreamCallback<UnixStream>>(
+    listener: &UnixListener,
+    callback: C,
+    config: Option<WebSocketConfig>,
+) -> Result<WebSocket<'a, UnixStream>, HandshakeError<ServerHandshake<UnixStream, C>>> {
+    let (stream, _) = listener.accept().map_err(Error::Io)?;
+    ServerHandshake::start(stream, callback, config).with_stream_timeouts().handshake()
+}
@@ -0,0 +1,273 @@
+//! A vector that either grows on the heap or is confined to borrowed storage.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+use managed::ManagedSlice;
+
+use super::Full;
+use crate::io::{Result as IoResult, Write};
+
+/// A vector backed by a [`ManagedSlice`].
+///
+/// Owned storage grows like a `Vec`. Borrowed storage never allocates: the vector holds at
+/// most as many elements as the slice, and growing past that fails with [`Full`]. Without the
+/// `alloc` feature only borrowed storage exists.
+pub struct ManagedVec<'a, T: 'a> {
+    storage: ManagedSlice<'a, T>,
+    /// Number of elements in use, only tracked for borrowed storage.
+    len: usize,
+}
+
+impl<'a, T: 'a> ManagedVec<'a, T> {
+    /// Create an empty vector that grows on the heap.
+    #[cfg(feature = "alloc")]
+    pub fn new() -> Self {
+        ManagedVec { storage: ManagedSlice::Owned(Vec::new()), len: 0 }
+    }
+
+    /// Create an empty vector that grows on the heap, allocating `capacity` elements upfront.
+    #[cfg(feature = "alloc")]
+    pub fn with_capacity(capacity: usize) -> Self {
+        ManagedVec { storage: ManagedSlice::Owned(Vec::with_capacity(capacity)), len: 0 }
+    }
+
+    /// Wrap `slice` as a vector holding all of its elements.
+    pub fn from_filled(slice: &'a mut [T]) -> Self {
+        let len = slice.len();
+        ManagedVec { storage: ManagedSlice::Borrowed(slice), len }
+    }
+
+    /// Return the number of elements in the vector.
+    pub fn len(&self) -> usize {
+        match self.storage {
+            ManagedSlice::Borrowed(_) => self.len,
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref vec) => vec.len(),
+        }
+    }
+
+    /// Query whether the vector is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the most elements the vector can hold, `usize::MAX` if it grows on the heap.
+    pub fn max_len(&self) -> usize {
+        match self.storage {
+            ManagedSlice::Borrowed(ref slice) => slice.len(),
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(_) => usize::MAX,
+        }
+    }
+
+    /// Return the number of elements the vector holds without allocating.
+    pub fn capacity(&self) -> usize {
+        match self.storage {
+            ManagedSlice::Borrowed(ref slice) => slice.len(),
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref vec) => vec.capacity(),
+        }
+    }
+
+    /// Remove all elements and free heap storage. Borrowed storage is kept.
+    pub fn release(&mut self) {
+        match self.storage {
+            ManagedSlice::Borrowed(_) => self.len = 0,
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref mut vec) => *vec = Vec::new(),
+        }
+    }
+
+    /// Remove all elements.
+    pub fn clear(&mut self) {
+        match self.storage {
+            ManagedSlice::Borrowed(_) => self.len = 0,
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref mut vec) => vec.clear(),
+        }
+    }
+
+    /// Reserve room for `additional` more elements if the vector grows on the heap.
+    pub fn reserve(&mut self, additional: usize) {
+        #[cfg(feature = "alloc")]
+        if let ManagedSlice::Owned(ref mut vec) = self.storage {
+            vec.reserve(additional);
+        }
+        #[cfg(not(feature = "alloc"))]
+        let _ = additional;
+    }
+
+    /// Append the elements of `data`, or return `Err(Full)` without appending any of them if
+    /// they do not fit.
+    pub fn extend_from_slice(&mut self, data: &[T]) -> Result<(), Full>
+    where
+        T: Clone,
+    {
+        match self.storage {
+            ManagedSlice::Borrowed(ref mut slice) => {
+                let end = self.len.checked_add(data.len()).filter(|&end| end <= slice.len());
+                let end = end.ok_or(Full)?;
+                slice[self.len..end].clone_from_slice(data);
+                self.len = end;
+            }
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref mut vec) => vec.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    /// Resize the vector to `new_len` elements, filling new ones with `value`, or return
+    /// `Err(Full)` without resizing if they do not fit.
+    pub fn resize(&mut self, new_len: usize, value: T) -> Result<(), Full>
+    where
+        T: Clone,
+    {
+        match self.storage {
+            ManagedSlice::Borrowed(ref mut slice) => {
+                if new_len > self.len {
+                    slice.get_mut(self.len..new_len).ok_or(Full)?.fill(value);
+                }
+                self.len = new_len;
+            }
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref mut vec) => vec.resize(new_len, value),
+        }
+        Ok(())
+    }
+
+    /// Remove the first `count` elements, moving the others to the front.
+    ///
+    /// # Panics
+    /// Panics if `count` is larger than the length of the vector.
+    pub fn drain_front(&mut self, count: usize)
+    where
+        T: Copy,
+    {
+        match self.storage {
+            ManagedSlice::Borrowed(ref mut slice) => {
+                assert!(count <= self.len, "Bug: draining past the end of the vector");
+                slice.copy_within(count..self.len, 0);
+                self.len -= count;
+            }
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref mut vec) => {
+                vec.drain(..count);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, T: 'a> Default for ManagedVec<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, T: 'a> From<Vec<T>> for ManagedVec<'a, T> {
+    fn from(vec: Vec<T>) -> Self {
+        ManagedVec { storage: ManagedSlice::Owned(vec), len: 0 }
+    }
+}
+
+impl<'a, T: 'a> From<&'a mut [T]> for ManagedVec<'a, T> {
+    fn from(slice: &'a mut [T]) -> Self {
+        ManagedVec { storage: ManagedSlice::Borrowed(slice), len: 0 }
+    }
+}
+
+impl<'a, T: 'a> Deref for ManagedVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self.storage {
+            ManagedSlice::Borrowed(ref slice) => &slice[..self.len],
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref vec) => vec,
+        }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for ManagedVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self.storage {
+            ManagedSlice::Borrowed(ref mut slice) => &mut slice[..self.len],
+            #[cfg(feature = "alloc")]
+            ManagedSlice::Owned(ref mut vec) => vec,
+        }
+    }
+}
+
+/// Clones into a vector that grows on the heap, as borrowed storage cannot be shared.
+#[cfg(feature = "alloc")]
+impl<'a, T: Clone + 'a> Clone for ManagedVec<'a, T> {
+    fn clone(&self) -> Self {
+        self.to_vec().into()
+    }
+}
+
+impl<'a, T: PartialEq + 'a> PartialEq for ManagedVec<'a, T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<'a, T: Eq + 'a> Eq for ManagedVec<'a, T> {}
+
+impl<'a, T: fmt::Debug + 'a> fmt::Debug for ManagedVec<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Writes as much as fits, like writing into a `&mut [u8]`.
+impl<'a> Write for ManagedVec<'a, u8> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let len = buf.len().min(self.max_len() - self.len());
+        self.extend_from_slice(&buf[..len]).expect("Bug: checked the free space");
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrowed_storage_is_bounded() {
+        let mut storage = [0; 6];
+        let mut vec = ManagedVec::from(&mut storage[..]);
+        assert_eq!(vec.max_len(), 6);
+        assert_eq!(vec.extend_from_slice(b"abcd"), Ok(()));
+        assert_eq!(vec.extend_from_slice(b"efg"), Err(Full));
+        assert_eq!(&*vec, b"abcd");
+
+        assert_eq!(vec.write(b"efg").unwrap(), 2);
+        assert_eq!(&*vec, b"abcdef");
+
+        vec.drain_front(4);
+        assert_eq!(&*vec, b"ef");
+        assert_eq!(vec.clone(), ManagedVec::from(b"ef".to_vec()));
+        vec.clear();
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn owned_storage_grows() {
+        let mut vec = ManagedVec::new();
+        assert_eq!(vec.max_len(), usize::MAX);
+        assert_eq!(vec.extend_from_slice(&[1; 100]), Ok(()));
+        assert_eq!(vec.write(&[2; 100]).unwrap(), 100);
+        vec.drain_front(150);
+        assert_eq!(&*vec, &[2; 50][..]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Ring buffer storage that is mapped twice in a row, so any window into it is contiguous.
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    marker::PhantomData,
+    mem,
+    os::unix::io::RawFd,
+    ptr::{self, NonNull},
+    slice,
+};
+
+/// `len` elements backed by a `memfd`, mapped twice back to back.
+///
+/// Element `i` and element `i + len` share the same physical memory, so a window that wraps
+/// around the end of the storage can be borrowed as one slice.
+pub(crate) struct MirroredBuffer<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+// The mapping is owned exclusively, like the allocation of a `Vec`.
+unsafe impl<T: Send> Send for MirroredBuffer<T> {}
+unsafe impl<T: Sync> Sync for MirroredBuffer<T> {}
+
+impl MirroredBuffer<u8> {
+    /// Map `len` zeroed bytes twice. `len` must be a multiple of the page size.
+    pub(crate) fn new(len: usize) -> Result<Self> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if len == 0 || page_size <= 0 || len % page_size as usize != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Mirrored buffer length must be a multiple of the page size",
+            ));
+        }
+        let size = len.checked_mul(2).ok_or_else(|| Error::from(ErrorKind::InvalidInput))?;
+
+        unsafe {
+            let fd =
+                libc::memfd_create(b"tungstenite-ring-buffer\0".as_ptr().cast(), libc::MFD_CLOEXEC);
+            if fd < 0 {
+                return Err(Error::last_os_error());
+            }
+            let result = Self::map(fd, len, size);
+            // The mappings keep the memory alive on their own.
+            libc::close(fd);
+            let ptr = result?;
+            Ok(MirroredBuffer { ptr: NonNull::new_unchecked(ptr), len, _marker: PhantomData })
+        }
+    }
+
+    /// Reserve `size` bytes of address space, then map both halves onto the file.
+    unsafe fn map(fd: RawFd, len: usize, size: usize) -> Result<*mut u8> {
+        if libc::ftruncate(fd, len as libc::off_t) < 0 {
+            return Err(Error::last_os_error());
+        }
+        let base = libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        if base == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        for half in &[base, base.cast::<u8>().add(len).cast()] {
+            let mapped = libc::mmap(
+                *half,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                fd,
+                0,
+            );
+            if mapped != *half {
+                let err = Error::last_os_error();
+                libc::munmap(base, size);
+                return Err(err);
+            }
+        }
+        Ok(base.cast())
+    }
+}
+
+impl<T> MirroredBuffer<T> {
+    /// Borrow the storage once.
+    pub(crate) fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Mutably borrow the storage once.
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Borrow `len` elements starting at `start`, which may extend into the mirror.
+    ///
+    /// # Panics
+    /// Panics if the window does not start in the storage or is longer than it.
+    pub(crate) fn window(&self, start: usize, len: usize) -> &[T] {
+        assert!(start < self.len && len <= self.len);
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr().add(start), len) }
+    }
+
+    /// Mutably borrow `len` elements starting at `start`, which may extend into the mirror.
+    ///
+    /// # Panics
+    /// Panics if the window does not start in the storage or is longer than it.
+    pub(crate) fn window_mut(&mut self, start: usize, len: usize) -> &mut [T] {
+        assert!(start < self.len && len <= self.len);
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr().add(start), len) }
+    }
+}
+
+impl<T> Drop for MirroredBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), 2 * self.len * mem::size_of::<T>());
+        }
+    }
+}
+
+impl<T> fmt::Debug for MirroredBuffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MirroredBuffer").field("len", &self.len).finish()
+    }
+}
@@ -1,12 +1,27 @@
 
-use std::io::{Error, ErrorKind};
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
 use byteorder::ByteOrder;
+use core::fmt;
+
+use crate::{
+    io::{Error, ErrorKind, IoSliceMut, Read},
+    protocol::frame::STATIC_SCRATCH_LEN,
+};
 
 use self::ring_buffer::RingBuffer;
 
+#[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+mod mirror;
+#[cfg(feature = "std")]
+pub mod pool;
+pub mod managed_vec;
 ///
 pub mod ring_buffer;
 
+#[cfg(feature = "std")]
+pub use self::pool::{BufferPool, PoolStats};
+
 /// A trait for setting a value to a known state.
 ///
 /// In-place analog of Default.
@@ -26,16 +41,139 @@ pub struct Full;
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Empty;
 
+/// Storage for a [`WebSocketContext`] that never allocates on the data path, see its
+/// [`from_static_buffers`](crate::protocol::WebSocketContext::from_static_buffers) constructor.
+///
+/// * `READ` bytes buffer the stream, so a frame header and its payload have to fit together.
+/// * `MESSAGE` bytes collect the payloads of a message's frames.
+/// * `WRITE` bytes buffer queued frames, e.g. pongs, until they are written to the stream.
+///
+/// A frame or message that does not fit fails with
+/// [`CapacityError::MessageTooLong`](crate::error::CapacityError::MessageTooLong), and a frame
+/// that cannot be queued with [`Error::WriteBufferFull`](crate::Error::WriteBufferFull).
+///
+/// [`WebSocketContext`]: crate::protocol::WebSocketContext
+pub struct StaticBuffers<const READ: usize, const MESSAGE: usize, const WRITE: usize> {
+    pub(crate) read: [u8; READ],
+    pub(crate) message: [u8; MESSAGE],
+    pub(crate) write: [u8; WRITE],
+    /// Masked payloads are copied here in chunks before they are written.
+    pub(crate) mask: [u8; STATIC_SCRATCH_LEN],
+}
+
+impl<const READ: usize, const MESSAGE: usize, const WRITE: usize>
+    StaticBuffers<READ, MESSAGE, WRITE>
+{
+    /// Create zeroed buffers, e.g. to initialize a `static`.
+    pub const fn new() -> Self {
+        StaticBuffers {
+            read: [0; READ],
+            message: [0; MESSAGE],
+            write: [0; WRITE],
+            mask: [0; STATIC_SCRATCH_LEN],
+        }
+    }
+}
+
+impl<const READ: usize, const MESSAGE: usize, const WRITE: usize> fmt::Debug
+    for StaticBuffers<READ, MESSAGE, WRITE>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticBuffers")
+            .field("read", &READ)
+            .field("message", &MESSAGE)
+            .field("write", &WRITE)
+            .finish()
+    }
+}
+
+impl<const READ: usize, const MESSAGE: usize, const WRITE: usize> Default
+    for StaticBuffers<READ, MESSAGE, WRITE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Capacity of the read buffer, a multiple of the common page sizes.
+#[cfg(feature = "alloc")]
+const CAPACITY: usize = 65536;
+
 #[derive(Debug)]
 pub struct U8RingBuffer<'a>(pub RingBuffer<'a,u8>);
 
 
 impl<'a> U8RingBuffer<'a> {
 
+    /// The read buffer a connection keeps for itself.
+    ///
+    /// With the `mirrored-buffer` feature it is mapped twice, which costs a `memfd` and two
+    /// mappings, so handshakes and pooled buffers use [`with_capacity`](Self::with_capacity).
+    #[cfg(feature = "alloc")]
     pub fn new() -> Self{
-        Self{
-            0:RingBuffer::new(vec![0;65536])
+        #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+        match RingBuffer::mirrored(CAPACITY) {
+            Ok(ring) => return Self(ring),
+            // `io::Error` only implements `core::fmt::Display`, so it is not logged through
+            // `protocol::logging`.
+            #[cfg(feature = "defmt")]
+            Err(err) => defmt::debug!(
+                "Falling back to an unmirrored read buffer: {}",
+                defmt::Display2Format(&err)
+            ),
+            #[cfg(not(feature = "defmt"))]
+            Err(err) => log::debug!("Falling back to an unmirrored read buffer: {}", err),
         }
+        Self::with_capacity(CAPACITY)
+    }
+
+    /// A plain read buffer of `capacity` bytes, never mirrored.
+    #[cfg(feature = "alloc")]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(RingBuffer::new(vec![0; capacity]))
+    }
+
+    /// Enlarge a plain buffer to `capacity` bytes, keeping the buffered bytes, see
+    /// [`RingBuffer::grow`].
+    #[cfg(feature = "alloc")]
+    pub fn grow(&mut self, capacity: usize) {
+        self.0.grow(capacity, 0);
+    }
+
+    /// Enlarge a plain buffer to the size of the buffers of [`new`](Self::new) if it is smaller.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn grow_to_default(&mut self) {
+        if self.0.capacity() < CAPACITY {
+            self.grow(CAPACITY);
+        }
+    }
+
+    /// A buffer without storage, for connections that have nothing buffered.
+    pub fn empty() -> Self {
+        Self(RingBuffer::new(&mut [][..]))
+    }
+
+    /// Read from `stream` into all of the free space in one call, including the space that
+    /// wraps around to the start of the buffer. Returns `Ok(0)` if the buffer is full.
+    pub fn read_from<S: Read>(&mut self, stream: &mut S) -> Result<usize, Error> {
+        if self.0.is_full() {
+            return Ok(0);
+        }
+        self.0
+            .enqueue_many_vectored_with(|first, second| {
+                let mut bufs = [IoSliceMut::new(first), IoSliceMut::new(second)];
+                match stream.read_vectored(&mut bufs) {
+                    Ok(size) => (size, Ok(size)),
+                    Err(err) => (0, Err(err)),
+                }
+            })
+            .1
+    }
+
+    /// Copy the buffered bytes out without dequeueing them.
+    #[cfg(feature = "alloc")]
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.peek_contiguous(self.0.len()).into_owned()
     }
 
     pub fn read_uint<B>(&mut self, nbytes: usize) -> Result<u64,Error>
@@ -43,18 +181,47 @@ impl<'a> U8RingBuffer<'a> {
         B: ByteOrder,
     {
         if self.0.len() >= nbytes {
-            let value = self.0.dequeue_many_contiguous(nbytes) ;
-            let int_value = match nbytes {
-                2 => B::read_u16(value) as u64,
-                3 => B::read_u24(value) as u64,
-                4 => B::read_u32(value) as u64,
-                6 => B::read_u48(value),
-                8 => B::read_u64(value),
-                _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid nbytes")),
+            let int_value = {
+                let mut value = [0; 8];
+                let value = &mut value[..nbytes.min(8)];
+                let _ = self.0.read_allocated(0, value);
+                match nbytes {
+                    2 => B::read_u16(value) as u64,
+                    3 => B::read_u24(value) as u64,
+                    4 => B::read_u32(value) as u64,
+                    6 => B::read_u48(value),
+                    8 => B::read_u64(value),
+                    _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid nbytes")),
+                }
             };
+            self.0.consume(nbytes);
             Ok(int_value)
         } else {
             Err(Error::new(ErrorKind::UnexpectedEof, "Not enough data"))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::U8RingBuffer;
+
+    #[test]
+    fn read_from_wraps_around() {
+        let mut buffer = U8RingBuffer::new();
+        let capacity = buffer.0.capacity();
+        assert_eq!(buffer.0.enqueue_slice(&vec![0; capacity - 10]), capacity - 10);
+        buffer.0.consume(capacity - 20);
+
+        // 10 bytes fit before the end of the buffer, the rest goes to its start.
+        let mut stream = Cursor::new((0..100).collect::<Vec<u8>>());
+        assert_eq!(buffer.read_from(&mut stream).unwrap(), 100);
+        assert_eq!(buffer.0.len(), 110);
+        assert_eq!(buffer.to_vec()[10..], *stream.get_ref());
+
+        buffer.0.consume(110);
+        assert_eq!(buffer.read_from(&mut stream).unwrap(), 0);
+    }
+}
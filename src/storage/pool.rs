@@ -0,0 +1,146 @@
+//! Read buffers shared by many connections.
+
+use std::sync::{Mutex, MutexGuard};
+
+use super::{ring_buffer::RingBuffer, U8RingBuffer};
+
+/// Statistics of a [`BufferPool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of buffers currently lent to connections.
+    pub in_use: usize,
+    /// Number of idle buffers kept for reuse.
+    pub idle: usize,
+    /// Number of buffers allocated because no idle one was available.
+    pub allocated: usize,
+    /// Number of times an idle buffer was lent again.
+    pub reused: usize,
+    /// Highest number of buffers lent at the same time.
+    pub peak_in_use: usize,
+}
+
+/// A pool of read buffers for many connections.
+///
+/// A connection configured with a pool (see
+/// [`WebSocketConfig::buffer_pool`](crate::protocol::WebSocketConfig::buffer_pool)) borrows
+/// a buffer only while a frame is partially received and returns it as soon as it has read
+/// everything that was buffered. Idle connections then hold no read buffer at all.
+///
+/// Each connection borrows at most one buffer of [`buffer_size`](Self::buffer_size) bytes,
+/// which also caps the size of the frames it can receive. The buffers are plain allocations,
+/// they are not mirrored even with the `mirrored-buffer` feature.
+///
+/// ```
+/// use tungstenite::{protocol::WebSocketConfig, storage::BufferPool};
+///
+/// let pool: &'static BufferPool = Box::leak(Box::new(BufferPool::new(16 * 1024)));
+/// let config = WebSocketConfig { buffer_pool: Some(pool), ..WebSocketConfig::default() };
+/// # let _ = config;
+/// ```
+#[derive(Debug)]
+pub struct BufferPool {
+    buffer_size: usize,
+    max_idle: usize,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    idle: Vec<Vec<u8>>,
+    stats: PoolStats,
+}
+
+impl BufferPool {
+    /// Create a pool of buffers of `buffer_size` bytes that keeps every returned buffer.
+    pub fn new(buffer_size: usize) -> Self {
+        BufferPool { buffer_size, max_idle: usize::MAX, state: Mutex::default() }
+    }
+
+    /// Keep at most `max_idle` returned buffers, free the others.
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// The size of every buffer, i.e. the most a connection buffers at once.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// A snapshot of the statistics of the pool.
+    pub fn stats(&self) -> PoolStats {
+        self.lock().stats
+    }
+
+    /// Lend a buffer, allocating it if no idle one is left.
+    pub(crate) fn acquire(&self) -> U8RingBuffer<'static> {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        let storage = match state.idle.pop() {
+            Some(storage) => {
+                state.stats.reused += 1;
+                storage
+            }
+            None => {
+                state.stats.allocated += 1;
+                vec![0; self.buffer_size]
+            }
+        };
+        let stats = &mut state.stats;
+        stats.idle = state.idle.len();
+        stats.in_use += 1;
+        stats.peak_in_use = stats.peak_in_use.max(stats.in_use);
+        U8RingBuffer(RingBuffer::new(storage))
+    }
+
+    /// Take back a buffer lent by [`acquire`](Self::acquire). Its contents are discarded.
+    pub(crate) fn release(&self, buffer: U8RingBuffer) {
+        let mut state = self.lock();
+        state.stats.in_use -= 1;
+        if let Some(storage) = buffer.0.into_vec() {
+            if state.idle.len() < self.max_idle && storage.len() == self.buffer_size {
+                state.idle.push(storage);
+            }
+        }
+        state.stats.idle = state.idle.len();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // The state is consistent after every update, so a panic elsewhere cannot corrupt it.
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferPool, PoolStats};
+
+    #[test]
+    fn lend_and_reuse() {
+        let pool = BufferPool::new(1024).with_max_idle(1);
+        let (first, second) = (pool.acquire(), pool.acquire());
+        assert_eq!(first.0.capacity(), 1024);
+        assert_eq!(
+            pool.stats(),
+            PoolStats { in_use: 2, idle: 0, allocated: 2, reused: 0, peak_in_use: 2 }
+        );
+
+        // Only one of the returned buffers is kept.
+        pool.release(first);
+        pool.release(second);
+        assert_eq!(
+            pool.stats(),
+            PoolStats { in_use: 0, idle: 1, allocated: 2, reused: 0, peak_in_use: 2 }
+        );
+
+        let mut buffer = pool.acquire();
+        assert!(buffer.0.is_empty());
+        assert_eq!(buffer.0.enqueue_slice(b"abc"), 3);
+        pool.release(buffer);
+        assert!(pool.acquire().0.is_empty());
+        assert_eq!(
+            pool.stats(),
+            PoolStats { in_use: 1, idle: 0, allocated: 2, reused: 2, peak_in_use: 2 }
+        );
+    }
+}
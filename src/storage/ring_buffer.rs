@@ -2,9 +2,16 @@
 // these functions may have side effects, and it's implemented by [RFC 1940].
 // [RFC 1940]: https://github.com/rust-lang/rust/issues/43302
 
-use core::cmp;
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, vec, vec::Vec};
+use core::{
+    cmp,
+    ops::{Deref, DerefMut},
+};
 use managed::ManagedSlice;
 
+#[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+use super::mirror::MirroredBuffer;
 use crate::storage::Resettable;
 
 use super::{Empty, Full};
@@ -23,17 +30,48 @@ use super::{Empty, Full};
 ///
 /// This implementation is suitable for both simple uses such as a FIFO queue
 /// of UDP packets, and advanced ones such as a TCP reassembly buffer.
-const MERGE_BUFFER_LEN:usize = 1000;
-const MOD: usize = 65535;
-const CAPACITY:usize =65536;
-
+///
+/// Elements that may wrap around the end of the storage are read with the
+/// "peek then consume" cursor: [`peek_contiguous`](Self::peek_contiguous) borrows them
+/// (or copies them if they wrap), and [`consume`](Self::consume) releases them afterwards.
+/// [`peek_contiguous_mut`](Self::peek_contiguous_mut) moves wrapped elements in place instead.
+/// A [mirrored](Self::mirrored) ring buffer never needs to copy them.
 #[derive(Debug)]
 pub struct RingBuffer<'a, T: 'a> {
-    storage: ManagedSlice<'a, T>,
+    storage: Storage<'a, T>,
     read_at: usize,
     length: usize,
-    // fix merge case,if fixed,please clear it
-    merge_buffer: Vec<T>, 
+}
+
+/// The elements of a ring buffer.
+#[derive(Debug)]
+enum Storage<'a, T: 'a> {
+    Slice(ManagedSlice<'a, T>),
+    /// Storage followed by a mirror of itself, see [`RingBuffer::mirrored`].
+    #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+    Mirrored(MirroredBuffer<T>),
+}
+
+impl<'a, T: 'a> Deref for Storage<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Storage::Slice(slice) => slice,
+            #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+            Storage::Mirrored(mirror) => mirror.as_slice(),
+        }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for Storage<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            Storage::Slice(slice) => slice,
+            #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+            Storage::Mirrored(mirror) => mirror.as_mut_slice(),
+        }
+    }
 }
 
 impl<'a, T: 'a> RingBuffer<'a, T> {
@@ -44,11 +82,51 @@ impl<'a, T: 'a> RingBuffer<'a, T> {
     where
         S: Into<ManagedSlice<'a, T>>,
     {
-        RingBuffer {
-            storage: storage.into(),
-            read_at: 0,
-            length: 0,
-            merge_buffer: Vec::with_capacity(MERGE_BUFFER_LEN)
+        RingBuffer { storage: Storage::Slice(storage.into()), read_at: 0, length: 0 }
+    }
+
+    /// Query whether the storage is [mirrored](RingBuffer::mirrored).
+    pub fn is_mirrored(&self) -> bool {
+        match self.storage {
+            Storage::Slice(_) => false,
+            #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+            Storage::Mirrored(_) => true,
+        }
+    }
+
+    /// Give back the storage if it is an owned `Vec`, discarding the elements in the buffer.
+    #[cfg(feature = "std")]
+    pub(crate) fn into_vec(self) -> Option<Vec<T>> {
+        match self.storage {
+            Storage::Slice(ManagedSlice::Owned(vec)) => Some(vec),
+            _ => None,
+        }
+    }
+
+    /// Enlarge the storage to `capacity` elements, filling the new ones with `value`.
+    ///
+    /// The allocated elements are kept, and only moved if they wrap around the end of the
+    /// storage. Owned storage is reallocated like a `Vec`, in place if the allocator can, and
+    /// storage without elements is replaced by an allocated one.
+    ///
+    /// # Panics
+    /// Panics if the storage is borrowed or mirrored, and has elements.
+    #[cfg(feature = "alloc")]
+    pub fn grow(&mut self, capacity: usize, value: T)
+    where
+        T: Copy,
+    {
+        if self.capacity() == 0 {
+            *self = RingBuffer::new(vec![value; capacity]);
+            return;
+        }
+        self.make_contiguous();
+        match self.storage {
+            Storage::Slice(ManagedSlice::Owned(ref mut vec)) if capacity > vec.len() => {
+                vec.resize(capacity, value)
+            }
+            Storage::Slice(ManagedSlice::Owned(_)) => {}
+            _ => panic!("Bug: only owned storage grows"),
         }
     }
 
@@ -79,22 +157,6 @@ impl<'a, T: 'a> RingBuffer<'a, T> {
         self.length
     }
 
-    /// Return the current number of elements in the ring buffer.
-    pub fn contigous_len(&self) -> usize {
-        cmp::min(self.len(), self.capacity() - self.read_at)
-    }
-
-    /// Return the current number of elements in the ring buffer.
-    pub fn position(&self) -> usize {
-        self.read_at
-    }
-
-    /// RollBack
-    pub fn rollback(&mut self,initial :usize,len: usize) {
-        self.read_at = initial;
-        self.length += len;
-    }
-
     /// Return the number of elements that can be added to the ring buffer.
     pub fn window(&self) -> usize {
         self.capacity() - self.len()
@@ -119,18 +181,30 @@ impl<'a, T: 'a> RingBuffer<'a, T> {
     /// Shorthand for `(self.read + idx) % self.capacity()` with an
     /// additional check to ensure that the capacity is not zero.
     fn get_idx(&self, idx: usize) -> usize {
-        //let len = self.capacity();
-        //if len > 0 {
-            (self.read_at + idx) & MOD
-        //} else {
-        //    0
-        //}
+        let len = self.capacity();
+        if len > 0 {
+            (self.read_at + idx) % len
+        } else {
+            0
+        }
     }
 
     /// Shorthand for `(self.read + idx) % self.capacity()` with no
     /// additional checks to ensure the capacity is not zero.
     fn get_idx_unchecked(&self, idx: usize) -> usize {
-        (self.read_at + idx) & MOD
+        (self.read_at + idx) % self.capacity()
+    }
+}
+
+#[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+impl RingBuffer<'static, u8> {
+    /// Create a ring buffer of `capacity` bytes whose storage is mapped twice in a row, so
+    /// elements that wrap around its end can still be borrowed as one slice.
+    ///
+    /// `capacity` must be a multiple of the page size.
+    pub fn mirrored(capacity: usize) -> std::io::Result<Self> {
+        let storage = Storage::Mirrored(MirroredBuffer::new(capacity)?);
+        Ok(RingBuffer { storage, read_at: 0, length: 0 })
     }
 }
 
@@ -219,6 +293,33 @@ impl<'a, T: 'a> RingBuffer<'a, T> {
         (size, result)
     }
 
+    /// Call `f` with all unallocated buffer elements, split into the slice up to the end
+    /// of the storage and the slice that wraps around to its start, and enqueue the amount
+    /// of elements returned by `f`.
+    ///
+    /// # Panics
+    /// This function panics if the amount of elements returned by `f` is larger
+    /// than the size of both slices passed into it.
+    pub fn enqueue_many_vectored_with<'b, R, F>(&'b mut self, f: F) -> (usize, R)
+    where
+        F: FnOnce(&'b mut [T], &'b mut [T]) -> (usize, R),
+    {
+        if self.length == 0 {
+            // Ring is currently empty. Reset `read_at` to optimize
+            // for contiguous space.
+            self.read_at = 0;
+        }
+
+        let write_at = self.get_idx(self.length);
+        let first_size = self.contiguous_window();
+        let second_size = self.window() - first_size;
+        let (wrapped, tail) = self.storage.split_at_mut(write_at);
+        let (size, result) = f(&mut tail[..first_size], &mut wrapped[..second_size]);
+        assert!(size <= first_size + second_size);
+        self.length += size;
+        (size, result)
+    }
+
     /// Enqueue a slice of elements up to the given size into the buffer,
     /// and return a reference to them.
     ///
@@ -266,8 +367,8 @@ impl<'a, T: 'a> RingBuffer<'a, T> {
         let capacity = self.capacity();
         let max_size = cmp::min(self.len(), capacity - self.read_at);
         let (size, result) = f(&mut self.storage[self.read_at..self.read_at + max_size]);
-        //assert!(size <= max_size);
-        self.read_at = (self.read_at + size) & MOD;
+        assert!(size <= max_size);
+        self.read_at = if capacity > 0 { (self.read_at + size) % capacity } else { 0 };
         self.length -= size;
         (size, result)
     }
@@ -286,122 +387,96 @@ impl<'a, T: 'a> RingBuffer<'a, T> {
         res.1
     }
 
-    #[must_use]
-    pub fn dequeue_many_no_leap(&mut self, size: usize) -> &mut [T] {
-        let res = self.dequeue_many_with(|buf| {
-            (size, &mut buf[..size])
-        });
-        res.1
+    /// Return the first `size` allocated elements without dequeueing them, or all of them
+    /// if fewer are allocated.
+    ///
+    /// The elements are borrowed from the storage if they are contiguous, and copied
+    /// otherwise, i.e. if they wrap around its end. The elements of a mirrored ring buffer
+    /// are always borrowed. Release them with [`consume`](Self::consume).
+    #[cfg(feature = "alloc")]
+    pub fn peek_contiguous(&self, size: usize) -> Cow<'_, [T]>
+    where
+        T: Clone,
+    {
+        let size = cmp::min(size, self.len());
+        let head = self.get_allocated(0, size);
+        if head.len() == size {
+            return Cow::Borrowed(head);
+        }
+        #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+        if let Storage::Mirrored(ref mirror) = self.storage {
+            return Cow::Borrowed(mirror.window(self.read_at, size));
+        }
+        let mut merged = Vec::with_capacity(size);
+        merged.extend_from_slice(head);
+        merged.extend_from_slice(self.get_allocated(head.len(), size - head.len()));
+        Cow::Owned(merged)
     }
 
-    // #[must_use]
-    // pub fn observe(&mut self, size: usize) -> &mut [T] {
-    //     let res = self.dequeue_many_with(|buf| {
-    //         (size, &mut buf[..size])
-    //     });
-    //     res.1
-    // }
-
-    pub fn dequeue_many_contiguous_with<F>(& mut self,size: usize,f:F)
+    /// Return the first `size` allocated elements without dequeueing them, or all of them
+    /// if fewer are allocated, as one mutable slice.
+    ///
+    /// Unlike [`peek_contiguous`](Self::peek_contiguous) this never copies the elements out:
+    /// if they wrap around the end of the storage, they are moved to its start first, see
+    /// [`make_contiguous`](Self::make_contiguous). The elements of a mirrored ring buffer are
+    /// always borrowed where they are. Release them with [`consume`](Self::consume).
+    pub fn peek_contiguous_mut(&mut self, size: usize) -> &mut [T]
     where
-        F: FnOnce(&mut [T]),T:Clone
+        T: Copy,
     {
-        unsafe {
-            let self_ptr:*mut Self = self;
-
-            let (real_data_len , data) = (*self_ptr).dequeue_many_with(|buf| {
-                let size = cmp::min(size, buf.len());
-                (size, &mut buf[..size])
-            });
-
-            if size == real_data_len {
-                f(data);
-                return;
-            }else{
-                let expected_extra_len = size - real_data_len;
-                let (_,extra_data) = (*self_ptr).dequeue_many_with(|buf| {
-                    let size = cmp::min(expected_extra_len, buf.len());
-                    (size, &mut buf[..size])
-                });
-                (*self_ptr).merge_buffer.clear();
-                (*self_ptr).merge_buffer.extend_from_slice(data);
-                (*self_ptr).merge_buffer.extend_from_slice(extra_data);
-                let slice = (*self_ptr).merge_buffer.as_mut_slice();
-                f(slice);
-            }
+        let size = cmp::min(size, self.len());
+        if self.get_allocated(0, size).len() < size && !self.is_mirrored() {
+            self.make_contiguous();
+        }
+        let start = self.read_at;
+        match self.storage {
+            Storage::Slice(ref mut slice) => &mut slice[start..start + size],
+            #[cfg(all(feature = "mirrored-buffer", target_os = "linux"))]
+            Storage::Mirrored(ref mut mirror) => mirror.window_mut(start, size),
         }
     }
 
-    pub fn dequeue_many_contiguous(& mut self,size: usize)-> &mut [T]
-    where 
-         T:Clone 
+    /// Move the allocated elements so they do not wrap around the end of the storage, like
+    /// `VecDeque::make_contiguous`. Only the allocated elements are copied, unless there is
+    /// too little free space left to move them through.
+    pub fn make_contiguous(&mut self)
+    where
+        T: Copy,
     {
-        // unsafe{
-        //     let self_ptr:*mut Self = self;
-        //     let data_len = cmp::min(size, self.contigous_len());
-        //     let data =&mut (*self_ptr).storage[self.read_at..self.read_at + data_len];
-        //     self.read_at = (self.read_at + data_len) & MOD;
-        //     self.length -= data_len;
-        
-        //     if size > data_len {
-        //         let expected_extra_len = size - data_len;
-        //         let extra_data_len = cmp::min(expected_extra_len, (*self_ptr).contigous_len());
-        //         let extra_data =&mut (*self_ptr).storage[self.read_at..self.read_at + extra_data_len];
-        //         self.read_at = (self.read_at + extra_data_len) & MOD;
-        //         self.length -= extra_data_len;
-    
-        //         self.merge_buffer.clear();
-        //         self.merge_buffer.extend_from_slice(data);
-        //         self.merge_buffer.extend_from_slice(extra_data);
-        //         self.merge_buffer.as_mut_slice()
-        //     }else{
-        //         data
-        //     }
-        // }
-        unsafe {
-            let self_ptr:*mut Self = self;
-  
-            let (real_data_len , data) = (*self_ptr).dequeue_many_with(|buf| {
-                let size = cmp::min(size, buf.len());
-                (size, &mut buf[..size])
-            });
-
-            if size > real_data_len {
-                let expected_extra_len = size - real_data_len;
-                let (_,extra_data) = (*self_ptr).dequeue_many_with(|buf| {
-                    let size = cmp::min(expected_extra_len, buf.len());
-                    (size, &mut buf[..size])
-                });
-                (*self_ptr).merge_buffer.clear();
-                (*self_ptr).merge_buffer.extend_from_slice(data);
-                (*self_ptr).merge_buffer.extend_from_slice(extra_data);
-                (*self_ptr).merge_buffer.as_mut_slice()
-            }else{
-                data
-            }
+        let capacity = self.capacity();
+        let head = cmp::min(self.length, capacity - self.read_at);
+        let tail = self.length - head;
+        let free = capacity - self.length;
+        if tail == 0 {
+            // Already contiguous.
+        } else if head <= free {
+            // Move the tail up by the length of the head, then the head to the start.
+            self.storage.copy_within(..tail, head);
+            self.storage.copy_within(self.read_at.., 0);
+            self.read_at = 0;
+        } else if tail <= free {
+            // Move the head down by the length of the tail, then the tail to the end.
+            let read_at = self.read_at - tail;
+            self.storage.copy_within(self.read_at.., read_at);
+            self.storage.copy_within(..tail, capacity - tail);
+            self.read_at = read_at;
+        } else {
+            let read_at = self.read_at;
+            self.storage.rotate_left(read_at);
+            self.read_at = 0;
         }
     }
 
-    pub fn dequeue_many_leap(& mut self,size: usize)-> &mut [T]
-    where 
-         T:Clone 
-    {
-        unsafe {
-            let self_ptr:*mut Self = self;
-  
-            let (real_data_len , data) = (*self_ptr).dequeue_many_with(|buf| {
-                let size = cmp::min(size, buf.len());
-                (size, &mut buf[..size])
-            });
-            let expected_extra_len = size - real_data_len;
-            let (_,extra_data) = (*self_ptr).dequeue_many_with(|buf| {
-                let size = cmp::min(expected_extra_len, buf.len());
-                (size, &mut buf[..size])
-            });
-            (*self_ptr).merge_buffer.clear();
-            (*self_ptr).merge_buffer.extend_from_slice(data);
-            (*self_ptr).merge_buffer.extend_from_slice(extra_data);
-            (*self_ptr).merge_buffer.as_mut_slice()
+    /// Dequeue the first `size` allocated elements, usually after they were
+    /// [peeked](Self::peek_contiguous).
+    ///
+    /// # Panics
+    /// Panics if `size` exceeds the number of allocated elements.
+    pub fn consume(&mut self, size: usize) {
+        self.dequeue_allocated(size);
+        if self.is_empty() {
+            // Reset `read_at` to keep the next enqueued elements contiguous.
+            self.read_at = 0;
         }
     }
 
@@ -708,6 +783,34 @@ mod test {
         assert_eq!(&ring.storage[..], b"abcdefghijkl");
     }
 
+    #[test]
+    fn test_buffer_enqueue_many_vectored_with() {
+        let mut ring = RingBuffer::new(vec![b'.'; 12]);
+        ring.enqueue_many_vectored_with(|first, second| {
+            assert_eq!((first.len(), second.len()), (12, 0));
+            first[..8].copy_from_slice(b"abcdefgh");
+            (8, ())
+        });
+        ring.consume(6);
+
+        // The free space wraps around: 4 elements at the end and 6 at the start.
+        let (size, ()) = ring.enqueue_many_vectored_with(|first, second| {
+            assert_eq!((first.len(), second.len()), (4, 6));
+            first.copy_from_slice(b"ijkl");
+            second[..3].copy_from_slice(b"mno");
+            (7, ())
+        });
+        assert_eq!(size, 7);
+        assert_eq!(&ring.storage[..], b"mnodefghijkl");
+        assert_eq!(&*ring.peek_contiguous(12), b"ghijklmno");
+
+        // Only the space before the first allocated element is left.
+        ring.enqueue_many_vectored_with(|first, second| {
+            assert_eq!((first.len(), second.len()), (3, 0));
+            (0, ())
+        });
+    }
+
     #[test]
     fn test_buffer_enqueue_slice() {
         let mut ring = RingBuffer::new(vec![b'.'; 12]);
@@ -908,19 +1011,21 @@ mod test {
         assert_eq!(&data[..], b"mno\x00\x00\x00");
     }
 
-    // #[test]
-    // fn test_buffer_with_no_capacity() {
-    //     let mut no_capacity: RingBuffer<u8> = RingBuffer::new(vec![]);
-
-    //     // Call all functions that calculate the remainder against rx_buffer.capacity()
-    //     // with a backing storage with a length of 0.
-    //     assert_eq!(no_capacity.get_unallocated(0, 0), &[]);
-    //     assert_eq!(no_capacity.get_allocated(0, 0), &[]);
-    //     no_capacity.dequeue_allocated(0);
-    //     assert_eq!(no_capacity.enqueue_many(0), &[]);
-    //     assert_eq!(no_capacity.enqueue_one(), Err(Full));
-    //     assert_eq!(no_capacity.contiguous_window(), 0);
-    // }
+    #[test]
+    fn test_buffer_with_no_capacity() {
+        let mut no_capacity: RingBuffer<u8> = RingBuffer::new(vec![]);
+
+        // Call all functions that calculate the remainder against rx_buffer.capacity()
+        // with a backing storage with a length of 0.
+        assert_eq!(no_capacity.get_unallocated(0, 0), &[]);
+        assert_eq!(no_capacity.get_allocated(0, 0), &[]);
+        no_capacity.dequeue_allocated(0);
+        assert_eq!(no_capacity.enqueue_many(0), &[]);
+        assert_eq!(no_capacity.enqueue_one(), Err(Full));
+        assert_eq!(no_capacity.contiguous_window(), 0);
+        assert_eq!(&*no_capacity.peek_contiguous(4), &[]);
+        no_capacity.consume(0);
+    }
 
     // Use the buffer a bit. Then empty it and put in an item of
     // maximum size. By detecting a length of 0, the implementation
@@ -938,4 +1043,179 @@ mod test {
         let large = ring.enqueue_many(8);
         assert_eq!(large.len(), 8);
     }
+
+    #[test]
+    fn test_buffer_peek_contiguous() {
+        let mut ring = RingBuffer::new(vec![b'.'; 12]);
+        assert_eq!(&*ring.peek_contiguous(4), b"");
+
+        assert_eq!(ring.enqueue_slice(b"abcdefgh"), 8);
+        assert!(matches!(ring.peek_contiguous(4), Cow::Borrowed(b"abcd")));
+        assert!(matches!(ring.peek_contiguous(16), Cow::Borrowed(b"abcdefgh")));
+        // Peeking does not dequeue.
+        assert_eq!(ring.len(), 8);
+
+        ring.consume(6);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.enqueue_slice(b"ijklmnop"), 8);
+        assert_eq!(&ring.storage[..], b"mnopefghijkl");
+
+        // "ghijkl" is contiguous, "klmn" wraps around the end of the storage.
+        assert!(matches!(ring.peek_contiguous(6), Cow::Borrowed(b"ghijkl")));
+        ring.consume(4);
+        match ring.peek_contiguous(4) {
+            Cow::Owned(merged) => assert_eq!(merged, b"klmn"),
+            Cow::Borrowed(_) => panic!("wrapped elements must be copied"),
+        }
+        assert_eq!(&*ring.peek_contiguous(16), b"klmnop");
+        ring.consume(3);
+        assert!(matches!(ring.peek_contiguous(3), Cow::Borrowed(b"nop")));
+    }
+
+    #[test]
+    fn test_buffer_peek_contiguous_mut() {
+        // Borrowed storage, as moving wrapped elements needs no allocation.
+        let mut storage = [b'.'; 8];
+        let mut ring = RingBuffer::new(&mut storage[..]);
+        assert_eq!(ring.peek_contiguous_mut(4), b"");
+
+        assert_eq!(ring.enqueue_slice(b"abcdef"), 6);
+        ring.consume(4);
+        assert_eq!(ring.enqueue_slice(b"ghij"), 4);
+        assert_eq!(&ring.storage[..], b"ijcdefgh");
+
+        // "efgh" is borrowed in place, "efghij" wraps and is moved first.
+        assert_eq!(ring.peek_contiguous_mut(4), b"efgh");
+        assert_eq!(&ring.storage[..], b"ijcdefgh");
+        ring.peek_contiguous_mut(16).make_ascii_uppercase();
+        assert_eq!(&ring.storage[2..], b"EFGHIJ");
+        assert_eq!(ring.len(), 6);
+
+        ring.consume(5);
+        assert_eq!(ring.enqueue_slice(b"klmnopq"), 7);
+        assert_eq!(ring.peek_contiguous_mut(8), b"Jklmnopq");
+    }
+
+    #[test]
+    fn test_buffer_make_contiguous() {
+        let cases: [(&[u8], &[u8], &[u8]); 3] = [
+            // The head fits into the free space, so the tail moves up and the head to the start.
+            (b"xxxxxxxxab", b"cd", b"abcdxxxxab"),
+            // Only the tail fits, so the head moves down and the tail to the end.
+            (b"xxxxabcdef", b"gh", b"ghabcdefgh"),
+            // Neither fits, so the storage is rotated.
+            (b"xxxxxabcde", b"fghi", b"abcdefghix"),
+        ];
+        for &(filled, wrapped, storage) in &cases {
+            let mut ring = RingBuffer::new(vec![b'.'; 10]);
+            assert_eq!(ring.enqueue_slice(filled), 10);
+            ring.consume(filled.iter().filter(|&&b| b == b'x').count());
+            assert_eq!(ring.enqueue_slice(wrapped), wrapped.len());
+            let elements = ring.peek_contiguous(10).into_owned();
+
+            ring.make_contiguous();
+            assert_eq!(&ring.storage[..], storage);
+            assert_eq!(ring.get_allocated(0, 10), &elements[..]);
+        }
+    }
+
+    #[test]
+    fn test_buffer_grow() {
+        let mut ring = RingBuffer::new(&mut [][..]);
+        ring.grow(4, b'.');
+        assert_eq!(&ring.storage[..], b"....");
+
+        // The wrapped elements are made contiguous, the others stay where they are.
+        assert_eq!(ring.enqueue_slice(b"abcd"), 4);
+        ring.consume(3);
+        assert_eq!(ring.enqueue_slice(b"e"), 1);
+        ring.grow(6, b'.');
+        assert_eq!(&ring.storage[..], b"decd..");
+        assert_eq!(ring.enqueue_slice(b"fghi"), 4);
+        assert_eq!(&*ring.peek_contiguous(6), b"defghi");
+    }
+
+    #[test]
+    fn test_buffer_consume() {
+        let mut ring = RingBuffer::new(vec![b'.'; 8]);
+        assert_eq!(ring.enqueue_slice(b"abcdef"), 6);
+        ring.consume(6);
+        assert!(ring.is_empty());
+        // Consuming everything rewinds, so the whole storage is contiguous again.
+        assert_eq!(ring.contiguous_window(), 8);
+        assert_eq!(ring.enqueue_slice(b"ABCDEFGH"), 8);
+        assert!(matches!(ring.peek_contiguous(8), Cow::Borrowed(b"ABCDEFGH")));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_buffer_consume_too_much() {
+        let mut ring = RingBuffer::new(vec![b'.'; 8]);
+        assert_eq!(ring.enqueue_slice(b"abc"), 3);
+        ring.consume(4);
+    }
+
+    // Stream bytes through buffers of every small capacity, enqueueing and consuming in
+    // differently sized steps so every wraparound offset is hit. Small enough to run under Miri.
+    #[test]
+    fn test_buffer_peek_consume_wraparound() {
+        let input: Vec<u8> = (0..64).collect();
+        for capacity in 1..=7 {
+            for put in 1..=capacity {
+                for take in 1..=capacity {
+                    let mut ring = RingBuffer::new(vec![0u8; capacity]);
+                    let (mut written, mut output) = (0, Vec::new());
+                    while output.len() < input.len() {
+                        let end = cmp::min(written + put, input.len());
+                        written += ring.enqueue_slice(&input[written..end]);
+
+                        let size = {
+                            let peeked = ring.peek_contiguous(take);
+                            assert_eq!(peeked.len(), cmp::min(take, ring.len()));
+                            output.extend_from_slice(&peeked);
+                            peeked.len()
+                        };
+                        assert_eq!(ring.peek_contiguous_mut(take), &output[output.len() - size..]);
+                        ring.consume(size);
+                    }
+                    assert_eq!(output, input, "capacity {}, put {}, take {}", capacity, put, take);
+                }
+            }
+        }
+    }
+
+    // Miri does not support `memfd_create`.
+    #[cfg(all(feature = "mirrored-buffer", target_os = "linux", not(miri)))]
+    #[test]
+    fn test_buffer_mirrored() {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        assert!(RingBuffer::mirrored(page_size - 1).is_err());
+
+        let mut ring = RingBuffer::mirrored(page_size).unwrap();
+        assert!(ring.is_mirrored());
+        assert_eq!(ring.capacity(), page_size);
+
+        let data: Vec<u8> = (0..page_size).map(|i| i as u8).collect();
+        let end = page_size - 96;
+        assert_eq!(ring.enqueue_slice(&data[..end]), end);
+        ring.consume(end - 10);
+        assert_eq!(ring.enqueue_slice(&data[..100]), 100);
+
+        // The last 4 elements wrap around the end of the storage, yet all of them are borrowed
+        // from the mirror.
+        match ring.peek_contiguous(110) {
+            Cow::Borrowed(window) => {
+                assert_eq!(&window[..10], &data[end - 10..end]);
+                assert_eq!(&window[10..], &data[..100]);
+            }
+            Cow::Owned(_) => panic!("mirrored elements must not be copied"),
+        }
+        // Nor moved, when they are borrowed mutably.
+        let read_at = ring.read_at;
+        ring.peek_contiguous_mut(110)[10..].copy_from_slice(&[0; 100]);
+        assert_eq!(ring.read_at, read_at);
+        assert_eq!(&ring.storage[..4], &[0; 4]);
+        ring.consume(110);
+        assert!(ring.is_empty());
+    }
 }
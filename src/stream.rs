@@ -6,12 +6,17 @@
 
 #[cfg(feature = "__rustls-tls")]
 use std::ops::Deref;
+#[cfg(feature = "native-tls")]
+use std::io;
 use std::{
     fmt::{self, Debug},
     io::{Read, Result as IoResult, Write},
+    time::Duration,
 };
 
 use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 
 #[cfg(feature = "native-tls")]
 use native_tls_crate::TlsStream;
@@ -39,6 +44,14 @@ impl NoDelay for TcpStream {
     }
 }
 
+/// Unix domain sockets have no Nagle algorithm, so there is nothing to switch.
+#[cfg(unix)]
+impl NoDelay for UnixStream {
+    fn set_nodelay(&mut self, _nodelay: bool) -> IoResult<()> {
+        Ok(())
+    }
+}
+
 #[cfg(feature = "native-tls")]
 impl<S: Read + Write + NoDelay> NoDelay for TlsStream<S> {
     fn set_nodelay(&mut self, nodelay: bool) -> IoResult<()> {
@@ -58,6 +71,47 @@ where
     }
 }
 
+/// Trait to set the read and write timeouts, used to enforce the handshake deadline on
+/// blocking streams (see `MidHandshake::with_stream_timeouts`).
+pub trait SetTimeout {
+    /// Set the read and the write timeout to the given value, `None` blocks indefinitely.
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()>;
+}
+
+impl SetTimeout for TcpStream {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(unix)]
+impl SetTimeout for UnixStream {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write + SetTimeout> SetTimeout for TlsStream<S> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.get_mut().set_timeout(timeout)
+    }
+}
+
+#[cfg(feature = "__rustls-tls")]
+impl<S, SD, T> SetTimeout for StreamOwned<S, T>
+where
+    S: Deref<Target = rustls::ConnectionCommon<SD>>,
+    SD: rustls::SideData,
+    T: Read + Write + SetTimeout,
+{
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.sock.set_timeout(timeout)
+    }
+}
+
 /// A stream that might be protected with TLS.
 #[non_exhaustive]
 pub enum MaybeTlsStream<S: Read + Write> {
@@ -69,6 +123,137 @@ pub enum MaybeTlsStream<S: Read + Write> {
     #[cfg(feature = "__rustls-tls")]
     /// Encrypted socket stream using `rustls`.
     Rustls(rustls::StreamOwned<rustls::ClientConnection, S>),
+    #[cfg(feature = "native-tls")]
+    /// Encrypted server socket stream using `native-tls` whose TLS handshake has been
+    /// interrupted and is completed by the following reads and writes.
+    NativeTlsAccept(NativeTlsAccept<S>),
+    #[cfg(feature = "__rustls-tls")]
+    /// Encrypted server socket stream using `rustls`.
+    RustlsServer(rustls::StreamOwned<rustls::ServerConnection, S>),
+    #[cfg(unix)]
+    /// Unix domain socket stream, used by [`connect`](crate::connect) for `ws+unix://` URLs.
+    Unix(UnixStream),
+}
+
+/// A server-side `native-tls` stream whose handshake would have blocked.
+///
+/// `native-tls` performs the handshake eagerly, so the interrupted handshake is resumed by the
+/// next read or write, which report `WouldBlock` until it has completed. This allows the TLS and
+/// the WebSocket handshake to be driven together on non-blocking sockets.
+#[cfg(feature = "native-tls")]
+pub struct NativeTlsAccept<S>(NativeTlsAcceptState<S>);
+
+#[cfg(feature = "native-tls")]
+enum NativeTlsAcceptState<S> {
+    Handshaking(Option<native_tls_crate::MidHandshakeTlsStream<S>>),
+    Established(TlsStream<S>),
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write> NativeTlsAccept<S> {
+    pub(crate) fn new(mid: native_tls_crate::MidHandshakeTlsStream<S>) -> Self {
+        NativeTlsAccept(NativeTlsAcceptState::Handshaking(Some(mid)))
+    }
+
+    /// Returns the TLS stream once the handshake has completed.
+    pub fn get_ref(&self) -> Option<&TlsStream<S>> {
+        match &self.0 {
+            NativeTlsAcceptState::Established(s) => Some(s),
+            NativeTlsAcceptState::Handshaking(_) => None,
+        }
+    }
+
+    fn established(&mut self) -> IoResult<&mut TlsStream<S>> {
+        use native_tls_crate::HandshakeError;
+
+        if let NativeTlsAcceptState::Handshaking(mid) = &mut self.0 {
+            let mid = mid.take().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotConnected, "TLS handshake has failed")
+            })?;
+            match mid.handshake() {
+                Ok(s) => self.0 = NativeTlsAcceptState::Established(s),
+                Err(HandshakeError::WouldBlock(mid)) => {
+                    self.0 = NativeTlsAcceptState::Handshaking(Some(mid));
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                // Turned back into `TlsError::Native` by `From<io::Error> for Error`.
+                Err(HandshakeError::Failure(e)) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, e))
+                }
+            }
+        }
+        match &mut self.0 {
+            NativeTlsAcceptState::Established(s) => Ok(s),
+            NativeTlsAcceptState::Handshaking(_) => unreachable!("Bug: TLS handshake not done"),
+        }
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Debug> Debug for NativeTlsAccept<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            NativeTlsAcceptState::Handshaking(mid) => {
+                f.debug_tuple("NativeTlsAccept::Handshaking").field(mid).finish()
+            }
+            NativeTlsAcceptState::Established(s) => {
+                f.debug_tuple("NativeTlsAccept::Established").field(s).finish()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write> Read for NativeTlsAccept<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.established()?.read(buf)
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write> Write for NativeTlsAccept<S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.established()?.write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.established()?.flush()
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write + NoDelay> NoDelay for NativeTlsAccept<S> {
+    fn set_nodelay(&mut self, nodelay: bool) -> IoResult<()> {
+        match &mut self.0 {
+            NativeTlsAcceptState::Handshaking(Some(mid)) => mid.get_mut().set_nodelay(nodelay),
+            NativeTlsAcceptState::Handshaking(None) => Ok(()),
+            NativeTlsAcceptState::Established(s) => s.set_nodelay(nodelay),
+        }
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write + SetTimeout> SetTimeout for NativeTlsAccept<S> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        match &mut self.0 {
+            NativeTlsAcceptState::Handshaking(Some(mid)) => mid.get_mut().set_timeout(timeout),
+            NativeTlsAcceptState::Handshaking(None) => Ok(()),
+            NativeTlsAcceptState::Established(s) => s.set_timeout(timeout),
+        }
+    }
+}
+
+#[cfg(feature = "__rustls-tls")]
+struct RustlsStreamDebug<'a, C, S: Read + Write>(&'a rustls::StreamOwned<C, S>);
+
+#[cfg(feature = "__rustls-tls")]
+impl<'a, C: Debug, S: Read + Write + Debug> Debug for RustlsStreamDebug<'a, C, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamOwned")
+            .field("conn", &self.0.conn)
+            .field("sock", &self.0.sock)
+            .finish()
+    }
 }
 
 impl<S: Read + Write + Debug> Debug for MaybeTlsStream<S> {
@@ -79,21 +264,18 @@ impl<S: Read + Write + Debug> Debug for MaybeTlsStream<S> {
             Self::NativeTls(s) => f.debug_tuple("MaybeTlsStream::NativeTls").field(s).finish(),
             #[cfg(feature = "__rustls-tls")]
             Self::Rustls(s) => {
-                struct RustlsStreamDebug<'a, S: Read + Write>(
-                    &'a rustls::StreamOwned<rustls::ClientConnection, S>,
-                );
-
-                impl<'a, S: Read + Write + Debug> Debug for RustlsStreamDebug<'a, S> {
-                    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-                        f.debug_struct("StreamOwned")
-                            .field("conn", &self.0.conn)
-                            .field("sock", &self.0.sock)
-                            .finish()
-                    }
-                }
-
                 f.debug_tuple("MaybeTlsStream::Rustls").field(&RustlsStreamDebug(s)).finish()
             }
+            #[cfg(feature = "native-tls")]
+            Self::NativeTlsAccept(s) => {
+                f.debug_tuple("MaybeTlsStream::NativeTlsAccept").field(s).finish()
+            }
+            #[cfg(feature = "__rustls-tls")]
+            Self::RustlsServer(s) => {
+                f.debug_tuple("MaybeTlsStream::RustlsServer").field(&RustlsStreamDebug(s)).finish()
+            }
+            #[cfg(unix)]
+            Self::Unix(s) => f.debug_tuple("MaybeTlsStream::Unix").field(s).finish(),
         }
     }
 }
@@ -106,6 +288,12 @@ impl<S: Read + Write> Read for MaybeTlsStream<S> {
             MaybeTlsStream::NativeTls(ref mut s) => s.read(buf),
             #[cfg(feature = "__rustls-tls")]
             MaybeTlsStream::Rustls(ref mut s) => s.read(buf),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTlsAccept(ref mut s) => s.read(buf),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::RustlsServer(ref mut s) => s.read(buf),
+            #[cfg(unix)]
+            MaybeTlsStream::Unix(ref mut s) => s.read(buf),
         }
     }
 }
@@ -118,6 +306,12 @@ impl<S: Read + Write> Write for MaybeTlsStream<S> {
             MaybeTlsStream::NativeTls(ref mut s) => s.write(buf),
             #[cfg(feature = "__rustls-tls")]
             MaybeTlsStream::Rustls(ref mut s) => s.write(buf),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTlsAccept(ref mut s) => s.write(buf),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::RustlsServer(ref mut s) => s.write(buf),
+            #[cfg(unix)]
+            MaybeTlsStream::Unix(ref mut s) => s.write(buf),
         }
     }
 
@@ -128,6 +322,12 @@ impl<S: Read + Write> Write for MaybeTlsStream<S> {
             MaybeTlsStream::NativeTls(ref mut s) => s.flush(),
             #[cfg(feature = "__rustls-tls")]
             MaybeTlsStream::Rustls(ref mut s) => s.flush(),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTlsAccept(ref mut s) => s.flush(),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::RustlsServer(ref mut s) => s.flush(),
+            #[cfg(unix)]
+            MaybeTlsStream::Unix(ref mut s) => s.flush(),
         }
     }
 }
@@ -140,6 +340,30 @@ impl<S: Read + Write + NoDelay> NoDelay for MaybeTlsStream<S> {
             MaybeTlsStream::NativeTls(ref mut s) => s.set_nodelay(nodelay),
             #[cfg(feature = "__rustls-tls")]
             MaybeTlsStream::Rustls(ref mut s) => s.set_nodelay(nodelay),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTlsAccept(ref mut s) => s.set_nodelay(nodelay),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::RustlsServer(ref mut s) => s.set_nodelay(nodelay),
+            #[cfg(unix)]
+            MaybeTlsStream::Unix(ref mut s) => s.set_nodelay(nodelay),
+        }
+    }
+}
+
+impl<S: Read + Write + SetTimeout> SetTimeout for MaybeTlsStream<S> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        match *self {
+            MaybeTlsStream::Plain(ref mut s) => s.set_timeout(timeout),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(ref mut s) => s.set_timeout(timeout),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::Rustls(ref mut s) => s.set_timeout(timeout),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTlsAccept(ref mut s) => s.set_timeout(timeout),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::RustlsServer(ref mut s) => s.set_timeout(timeout),
+            #[cfg(unix)]
+            MaybeTlsStream::Unix(ref mut s) => s.set_timeout(timeout),
         }
     }
 }
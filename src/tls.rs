@@ -2,12 +2,16 @@
 use std::io::{Read, Write};
 
 use crate::{
-    client::{client_with_config, uri_mode, IntoClientRequest},
+    client::{client_with_config, uri_mode, IntoClientRequest, TlsOptions},
     error::UrlError,
-    handshake::client::Response,
+    handshake::{
+        client::{Request, Response},
+        server::{NoCallback, StreamCallback},
+    },
     protocol::WebSocketConfig,
+    server::accept_hdr_with_config,
     stream::MaybeTlsStream,
-    ClientHandshake, Error, HandshakeError, Result, WebSocket,
+    ClientHandshake, Error, HandshakeError, Result, ServerHandshake, WebSocket,
 };
 
 /// A connector that can be used when establishing connections, allowing to control whether
@@ -26,16 +30,58 @@ pub enum Connector {
     Rustls(std::sync::Arc<rustls::ClientConfig>),
 }
 
+impl Connector {
+    /// Creates a connector from [`TlsOptions`], e.g. to pin the certificate of the server with
+    /// `spki_sha256_pins` or to check it with a `verifier` callback.
+    ///
+    /// As with [`connect_with_options`](crate::client::connect_with_options), `native-tls` is
+    /// used if both backends are enabled, unless the certificate is verified with pins or a
+    /// callback. The `server_name` of the options is not applied, the connector is used for
+    /// the host of the request.
+    pub fn from_options(options: &TlsOptions) -> Result<Self> {
+        #[cfg(all(feature = "native-tls", feature = "__rustls-tls"))]
+        if options.custom_verification() {
+            return Ok(Connector::Rustls(self::encryption::rustls::client_config(options)?));
+        }
+        #[cfg(feature = "native-tls")]
+        {
+            Ok(Connector::NativeTls(self::encryption::native_tls::connector(options)?))
+        }
+        #[cfg(not(feature = "native-tls"))]
+        Ok(Connector::Rustls(self::encryption::rustls::client_config(options)?))
+    }
+}
+
+/// An acceptor used by [`accept_tls()`] to wrap accepted connections into TLS, either with
+/// `native-tls` or with `rustls`.
+#[non_exhaustive]
+#[allow(missing_debug_implementations)]
+pub enum Acceptor {
+    /// `native-tls` TLS acceptor.
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls_crate::TlsAcceptor),
+    /// `rustls` TLS acceptor.
+    #[cfg(feature = "__rustls-tls")]
+    Rustls(std::sync::Arc<rustls::ServerConfig>),
+}
+
 mod encryption {
     #[cfg(feature = "native-tls")]
     pub mod native_tls {
-        use native_tls_crate::{HandshakeError as TlsHandshakeError, TlsConnector};
+        use native_tls_crate::{
+            Certificate, HandshakeError as TlsHandshakeError, Identity, TlsAcceptor, TlsConnector,
+        };
 
-        use std::io::{Read, Write};
+        use std::{
+            io::{self, Read, Write},
+            result::Result as StdResult,
+            str,
+        };
 
         use crate::{
+            client::TlsOptions,
             error::TlsError,
-            stream::{MaybeTlsStream, Mode},
+            stream::{MaybeTlsStream, Mode, NativeTlsAccept},
             Error, Result,
         };
 
@@ -57,31 +103,82 @@ mod encryption {
                     match connected {
                         Err(e) => match e {
                             TlsHandshakeError::Failure(f) => Err(Error::Tls(f.into())),
-                            TlsHandshakeError::WouldBlock(_) => {
-                                panic!("Bug: TLS handshake not blocked")
-                            }
+                            // Blocking sockets only report this once a read or write
+                            // timeout has expired.
+                            TlsHandshakeError::WouldBlock(_) => Err(Error::Io(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "TLS handshake timed out",
+                            ))),
                         },
                         Ok(s) => Ok(MaybeTlsStream::NativeTls(s)),
                     }
                 }
             }
         }
+
+        pub fn connector(options: &TlsOptions) -> Result<TlsConnector> {
+            let mut builder = TlsConnector::builder();
+            for pem in &options.root_certs_pem {
+                for cert in Certificate::stack_from_pem(pem).map_err(TlsError::Native)? {
+                    builder.add_root_certificate(cert);
+                }
+            }
+            match (&options.client_cert_pem, &options.client_key_pem) {
+                (Some(cert), Some(key)) => {
+                    let identity = Identity::from_pkcs8(cert, key).map_err(TlsError::Native)?;
+                    builder.identity(identity);
+                }
+                (None, None) => {}
+                _ => return Err(TlsError::InvalidPem("client certificate without key").into()),
+            }
+            let alpn: Vec<&str> = options
+                .alpn_protocols
+                .iter()
+                .map(|protocol| str::from_utf8(protocol))
+                .collect::<StdResult<_, _>>()
+                .map_err(|_| TlsError::InvalidAlpnProtocol)?;
+            builder.request_alpns(&alpn);
+            Ok(builder.build().map_err(TlsError::Native)?)
+        }
+
+        pub fn accept_stream<S>(socket: S, acceptor: &TlsAcceptor) -> Result<MaybeTlsStream<S>>
+        where
+            S: Read + Write,
+        {
+            match acceptor.accept(socket) {
+                Ok(s) => Ok(MaybeTlsStream::NativeTls(s)),
+                Err(TlsHandshakeError::WouldBlock(mid)) => {
+                    Ok(MaybeTlsStream::NativeTlsAccept(NativeTlsAccept::new(mid)))
+                }
+                Err(TlsHandshakeError::Failure(f)) => Err(Error::Tls(f.into())),
+            }
+        }
     }
 
     #[cfg(feature = "__rustls-tls")]
     pub mod rustls {
-        use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerName, StreamOwned};
+        use rustls::{
+            client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+            Certificate, CertificateError, ClientConfig, ClientConnection, PrivateKey,
+            RootCertStore, ServerConfig, ServerConnection, ServerName, StreamOwned,
+        };
+        use rustls_pemfile::Item;
+        use sha2::{Digest, Sha256};
+        use yasna::{tags::TAG_SEQUENCE, ASN1Error, ASN1ErrorKind, Tag};
 
         use std::{
             convert::TryFrom,
-            io::{Read, Write},
+            io::{self, Read, Write},
+            result::Result as StdResult,
             sync::Arc,
+            time::SystemTime,
         };
 
         use crate::{
+            client::{CertVerifier, ServerCertificate, TlsOptions},
             error::TlsError,
             stream::{MaybeTlsStream, Mode},
-            Result,
+            Error, Result,
         };
 
         pub fn wrap_stream<S>(
@@ -98,40 +195,12 @@ mod encryption {
                 Mode::Tls => {
                     let config = match tls_connector {
                         Some(config) => config,
-                        None => {
-                            #[allow(unused_mut)]
-                            let mut root_store = RootCertStore::empty();
-
-                            #[cfg(feature = "rustls-tls-native-roots")]
-                            {
-                                let native_certs = rustls_native_certs::load_native_certs()?;
-                                let der_certs: Vec<Vec<u8>> =
-                                    native_certs.into_iter().map(|cert| cert.0).collect();
-                                let total_number = der_certs.len();
-                                let (number_added, number_ignored) =
-                                    root_store.add_parsable_certificates(&der_certs);
-                                log::debug!("Added {number_added}/{total_number} native root certificates (ignored {number_ignored})");
-                            }
-                            #[cfg(feature = "rustls-tls-webpki-roots")]
-                            {
-                                root_store.add_server_trust_anchors(
-                                    webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
-                                        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                                            ta.subject,
-                                            ta.spki,
-                                            ta.name_constraints,
-                                        )
-                                    })
-                                );
-                            }
-
-                            Arc::new(
-                                ClientConfig::builder()
-                                    .with_safe_defaults()
-                                    .with_root_certificates(root_store)
-                                    .with_no_client_auth(),
-                            )
-                        }
+                        None => Arc::new(
+                            ClientConfig::builder()
+                                .with_safe_defaults()
+                                .with_root_certificates(default_root_store()?)
+                                .with_no_client_auth(),
+                        ),
                     };
                     let domain =
                         ServerName::try_from(domain).map_err(|_| TlsError::InvalidDnsName)?;
@@ -142,6 +211,229 @@ mod encryption {
                 }
             }
         }
+
+        fn default_root_store() -> Result<RootCertStore> {
+            #[allow(unused_mut)]
+            let mut root_store = RootCertStore::empty();
+
+            #[cfg(feature = "rustls-tls-native-roots")]
+            {
+                let native_certs = rustls_native_certs::load_native_certs()?;
+                let der_certs: Vec<Vec<u8>> =
+                    native_certs.into_iter().map(|cert| cert.0).collect();
+                let total_number = der_certs.len();
+                let (number_added, number_ignored) =
+                    root_store.add_parsable_certificates(&der_certs);
+                log::debug!("Added {number_added}/{total_number} native root certificates (ignored {number_ignored})");
+            }
+            #[cfg(feature = "rustls-tls-webpki-roots")]
+            {
+                root_store.add_server_trust_anchors(
+                    webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                            ta.subject,
+                            ta.spki,
+                            ta.name_constraints,
+                        )
+                    })
+                );
+            }
+
+            Ok(root_store)
+        }
+
+        pub fn client_config(options: &TlsOptions) -> Result<Arc<ClientConfig>> {
+            let mut root_store = default_root_store()?;
+            for pem in &options.root_certs_pem {
+                for der in read_pem(pem)?.into_iter().filter_map(|item| match item {
+                    Item::X509Certificate(der) => Some(der),
+                    _ => None,
+                }) {
+                    root_store
+                        .add(&Certificate(der))
+                        .map_err(|_| TlsError::InvalidPem("invalid root certificate"))?;
+                }
+            }
+
+            let webpki = WebPkiVerifier::new(root_store, None);
+            let verifier: Arc<dyn ServerCertVerifier> = if options.custom_verification() {
+                Arc::new(CustomVerifier {
+                    webpki,
+                    pins: options.spki_sha256_pins.clone(),
+                    callback: options.verifier.clone(),
+                })
+            } else {
+                Arc::new(webpki)
+            };
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(verifier);
+            let mut config = match (&options.client_cert_pem, &options.client_key_pem) {
+                (Some(cert), Some(key)) => {
+                    let certs: Vec<_> = read_pem(cert)?
+                        .into_iter()
+                        .filter_map(|item| match item {
+                            Item::X509Certificate(der) => Some(Certificate(der)),
+                            _ => None,
+                        })
+                        .collect();
+                    let key = read_pem(key)?
+                        .into_iter()
+                        .find_map(|item| match item {
+                            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => {
+                                Some(PrivateKey(der))
+                            }
+                            _ => None,
+                        })
+                        .ok_or(TlsError::InvalidPem("no private key found"))?;
+                    if certs.is_empty() {
+                        return Err(TlsError::InvalidPem("no client certificate found").into());
+                    }
+                    builder.with_client_auth_cert(certs, key).map_err(TlsError::Rustls)?
+                }
+                (None, None) => builder.with_no_client_auth(),
+                _ => return Err(TlsError::InvalidPem("client certificate without key").into()),
+            };
+            config.alpn_protocols = options.alpn_protocols.clone();
+            Ok(Arc::new(config))
+        }
+
+        fn read_pem(pem: &[u8]) -> Result<Vec<Item>> {
+            rustls_pemfile::read_all(&mut &pem[..])
+                .map_err(|_| TlsError::InvalidPem("malformed PEM").into())
+        }
+
+        /// Verifies the certificate of the server with SPKI pins and a callback.
+        struct CustomVerifier {
+            webpki: WebPkiVerifier,
+            pins: Vec<[u8; 32]>,
+            callback: Option<CertVerifier>,
+        }
+
+        impl ServerCertVerifier for CustomVerifier {
+            fn verify_server_cert(
+                &self,
+                end_entity: &Certificate,
+                intermediates: &[Certificate],
+                server_name: &ServerName,
+                scts: &mut dyn Iterator<Item = &[u8]>,
+                ocsp_response: &[u8],
+                now: SystemTime,
+            ) -> StdResult<ServerCertVerified, rustls::Error> {
+                let spki_sha256 = spki_sha256(&end_entity.0)
+                    .ok_or(rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+                if !self.pins.is_empty() && !self.pins.contains(&spki_sha256) {
+                    return Err(rejected(TlsError::PinMismatch));
+                }
+                let trusted = self
+                    .webpki
+                    .verify_server_cert(
+                        end_entity,
+                        intermediates,
+                        server_name,
+                        scts,
+                        ocsp_response,
+                        now,
+                    )
+                    .is_ok();
+                if let Some(callback) = &self.callback {
+                    let certificate = ServerCertificate {
+                        server_name,
+                        end_entity,
+                        intermediates,
+                        spki_sha256,
+                        trusted,
+                    };
+                    if !callback(&certificate) {
+                        return Err(rejected(TlsError::CertificateRejected));
+                    }
+                }
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+
+        fn rejected(error: TlsError) -> rustls::Error {
+            rustls::Error::InvalidCertificate(CertificateError::Other(Arc::new(error)))
+        }
+
+        /// Completes the TLS handshake, so that a rejected certificate is reported as such
+        /// instead of as an I/O error of the WebSocket handshake.
+        pub fn handshake<S>(mut stream: MaybeTlsStream<S>) -> Result<MaybeTlsStream<S>>
+        where
+            S: Read + Write,
+        {
+            if let MaybeTlsStream::Rustls(tls) = &mut stream {
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock).map_err(handshake_error)?;
+                }
+            }
+            Ok(stream)
+        }
+
+        fn handshake_error(error: io::Error) -> Error {
+            match error.kind() {
+                // Blocking sockets only report this once a read or write timeout has expired.
+                io::ErrorKind::WouldBlock => {
+                    return Error::Io(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "TLS handshake timed out",
+                    ))
+                }
+                // rustls reports its errors as the payload, the stream may raise others.
+                io::ErrorKind::InvalidData if error.get_ref().is_some() => {}
+                _ => return Error::Io(error),
+            }
+            // Certificates rejected by the pins or the verifier are converted by `From`.
+            let error = match Error::from(error) {
+                Error::Io(error) => error,
+                rejected => return rejected,
+            };
+            let inner = error.into_inner().expect("Bug: checked for a payload");
+            match inner.downcast::<rustls::Error>() {
+                Ok(tls) => TlsError::Rustls(*tls).into(),
+                Err(inner) => Error::Io(io::Error::new(io::ErrorKind::InvalidData, inner)),
+            }
+        }
+
+        /// Returns the SHA-256 fingerprint of the SubjectPublicKeyInfo of a DER encoded
+        /// X.509 certificate.
+        fn spki_sha256(certificate: &[u8]) -> Option<[u8; 32]> {
+            let spki = yasna::parse_der(certificate, |certificate| {
+                certificate.read_sequence(|certificate| {
+                    let spki = certificate.next().read_sequence(|tbs| {
+                        tbs.read_optional(|version| {
+                            version.read_tagged(Tag::context(0), |version| version.read_u8())
+                        })?;
+                        // Serial number, signature algorithm, issuer, validity and subject.
+                        for _ in 0..5 {
+                            tbs.next().read_der()?;
+                        }
+                        let spki = tbs.next();
+                        if spki.lookahead_tag()? != TAG_SEQUENCE {
+                            return Err(ASN1Error::new(ASN1ErrorKind::Invalid));
+                        }
+                        let spki = spki.read_der()?;
+                        // Unique identifiers and extensions.
+                        while tbs.read_optional(|field| field.read_der())?.is_some() {}
+                        Ok(spki)
+                    })?;
+                    // Signature algorithm and signature.
+                    certificate.next().read_der()?;
+                    certificate.next().read_der()?;
+                    Ok(spki)
+                })
+            });
+            Some(Sha256::digest(spki.ok()?).into())
+        }
+
+        pub fn accept_stream<S>(socket: S, config: Arc<ServerConfig>) -> Result<MaybeTlsStream<S>>
+        where
+            S: Read + Write,
+        {
+            // The TLS handshake is driven by the reads and writes of the WebSocket handshake.
+            let server = ServerConnection::new(config).map_err(TlsError::Rustls)?;
+            Ok(MaybeTlsStream::RustlsServer(StreamOwned::new(server, socket)))
+        }
     }
 
     pub mod plain {
@@ -169,10 +461,10 @@ type TlsHandshakeError<S> = HandshakeError<ClientHandshake<MaybeTlsStream<S>>>;
 
 /// Creates a WebSocket handshake from a request and a stream,
 /// upgrading the stream to TLS if required.
-pub fn client_tls<R, S>(
+pub fn client_tls<'a, R, S>(
     request: R,
     stream: S,
-) -> Result<(WebSocket<MaybeTlsStream<S>>, Response), TlsHandshakeError<S>>
+) -> Result<(WebSocket<'a, MaybeTlsStream<S>>, Response), TlsHandshakeError<S>>
 where
     R: IntoClientRequest,
     S: Read + Write,
@@ -185,12 +477,12 @@ where
 /// be created.
 ///
 /// Please refer to [`client_tls()`] for more details.
-pub fn client_tls_with_config<R, S>(
+pub fn client_tls_with_config<'a, R, S>(
     request: R,
     stream: S,
     config: Option<WebSocketConfig>,
     connector: Option<Connector>,
-) -> Result<(WebSocket<MaybeTlsStream<S>>, Response), TlsHandshakeError<S>>
+) -> Result<(WebSocket<'a, MaybeTlsStream<S>>, Response), TlsHandshakeError<S>>
 where
     R: IntoClientRequest,
     S: Read + Write,
@@ -235,3 +527,100 @@ where
 
     client_with_config(request, stream, config)
 }
+
+/// Like [`client_tls_with_config()`], but the connector is built from the given options.
+pub(crate) fn client_tls_with_options<'a, S>(
+    request: Request,
+    stream: S,
+    config: Option<WebSocketConfig>,
+    options: &TlsOptions,
+) -> Result<(WebSocket<'a, MaybeTlsStream<S>>, Response), TlsHandshakeError<S>>
+where
+    S: Read + Write,
+{
+    let domain = match (&options.server_name, request.uri().host()) {
+        (Some(name), _) => name.clone(),
+        (None, Some(host)) => host.to_string(),
+        (None, None) => return Err(Error::Url(UrlError::NoHostName).into()),
+    };
+    let mode = uri_mode(request.uri())?;
+
+    // `native-tls` takes precedence if both backends are enabled, unless the certificate is
+    // verified with pins or a callback, which only `rustls` supports.
+    #[cfg(all(feature = "native-tls", feature = "__rustls-tls"))]
+    if options.custom_verification() {
+        return client_rustls_with_options(request, stream, &domain, mode, config, options);
+    }
+    #[cfg(feature = "native-tls")]
+    {
+        let connector = self::encryption::native_tls::connector(options)?;
+        let stream =
+            self::encryption::native_tls::wrap_stream(stream, &domain, mode, Some(connector))?;
+        client_with_config(request, stream, config)
+    }
+    #[cfg(not(feature = "native-tls"))]
+    client_rustls_with_options(request, stream, &domain, mode, config, options)
+}
+
+#[cfg(feature = "__rustls-tls")]
+fn client_rustls_with_options<'a, S>(
+    request: Request,
+    stream: S,
+    domain: &str,
+    mode: crate::stream::Mode,
+    config: Option<WebSocketConfig>,
+    options: &TlsOptions,
+) -> Result<(WebSocket<'a, MaybeTlsStream<S>>, Response), TlsHandshakeError<S>>
+where
+    S: Read + Write,
+{
+    let connector = self::encryption::rustls::client_config(options)?;
+    let stream = self::encryption::rustls::wrap_stream(stream, domain, mode, Some(connector))?;
+    client_with_config(request, self::encryption::rustls::handshake(stream)?, config)
+}
+
+type TlsAcceptError<S, C> = HandshakeError<ServerHandshake<MaybeTlsStream<S>, C>>;
+
+/// Accept the given stream as a WebSocket over TLS.
+///
+/// The stream, usually a freshly accepted `TcpStream`, is wrapped into TLS with the given
+/// acceptor and the WebSocket handshake is performed over it. On non-blocking streams a TLS
+/// handshake that would block is reported as [`HandshakeError::Interrupted`], just like the
+/// WebSocket handshake, and is resumed by [`MidHandshake::handshake`].
+///
+/// [`MidHandshake::handshake`]: crate::handshake::MidHandshake::handshake
+pub fn accept_tls<'a, S>(
+    stream: S,
+    acceptor: &Acceptor,
+) -> Result<WebSocket<'a, MaybeTlsStream<S>>, TlsAcceptError<S, NoCallback>>
+where
+    S: Read + Write,
+{
+    accept_tls_with_config(stream, acceptor, NoCallback, None)
+}
+
+/// The same as [`accept_tls()`] but one can specify a callback for header processing (see
+/// [`accept_hdr()`](crate::accept_hdr)) and a websocket configuration.
+pub fn accept_tls_with_config<'a, S, C>(
+    stream: S,
+    acceptor: &Acceptor,
+    callback: C,
+    config: Option<WebSocketConfig>,
+) -> Result<WebSocket<'a, MaybeTlsStream<S>>, TlsAcceptError<S, C>>
+where
+    S: Read + Write,
+    C: StreamCallback<MaybeTlsStream<S>>,
+{
+    let stream = match acceptor {
+        #[cfg(feature = "native-tls")]
+        Acceptor::NativeTls(acceptor) => {
+            self::encryption::native_tls::accept_stream(stream, acceptor)
+        }
+        #[cfg(feature = "__rustls-tls")]
+        Acceptor::Rustls(config) => {
+            self::encryption::rustls::accept_stream(stream, config.clone())
+        }
+    }?;
+
+    accept_hdr_with_config(stream, callback, config)
+}
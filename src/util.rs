@@ -1,12 +1,12 @@
 //! Helper traits to ease non-blocking handling.
 
-use std::{
+use core::result::Result as StdResult;
+
+use crate::{
+    error::Error,
     io::{Error as IoError, ErrorKind as IoErrorKind},
-    result::Result as StdResult,
 };
 
-use crate::error::Error;
-
 /// Non-blocking IO handling.
 pub trait NonBlockingError: Sized {
     /// Convert WouldBlock to None and don't touch other errors.
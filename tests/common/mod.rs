@@ -0,0 +1,18 @@
+//! Helpers shared by the integration tests.
+
+use std::{
+    process::exit,
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+/// Initializes logging and aborts the test binary if it runs for longer than five seconds.
+pub fn start_watchdog() {
+    env_logger::try_init().ok();
+
+    spawn(|| {
+        sleep(Duration::from_secs(5));
+        println!("Unit test executed too long, perhaps stuck on WOULDBLOCK...");
+        exit(1);
+    });
+}
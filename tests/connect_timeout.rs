@@ -0,0 +1,73 @@
+//! Verifies the connect and handshake timeouts of `connect_with_options`.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{
+    io,
+    net::TcpListener,
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
+};
+
+use tungstenite::{
+    accept,
+    client::{connect_with_options, ConnectOptions},
+    Error,
+};
+
+#[test]
+fn test_handshake_timeout() {
+    common::start_watchdog();
+
+    // Accepts the TCP connection but never answers the handshake.
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        let _socket = server.incoming().next().unwrap().unwrap();
+        sleep(Duration::from_secs(1));
+    });
+
+    let options = ConnectOptions {
+        handshake_timeout: Some(Duration::from_millis(200)),
+        ..ConnectOptions::default()
+    };
+    let url = format!("ws://127.0.0.1:{}/socket", port);
+    let started = Instant::now();
+    match connect_with_options(url, None, options) {
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::TimedOut => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    assert!(started.elapsed() < Duration::from_millis(900));
+
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn test_fallback_to_next_address() {
+    common::start_watchdog();
+
+    // `localhost` resolves to `::1` and `127.0.0.1`, only the latter is listening.
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        for stream in server.incoming().take(2) {
+            accept(stream.unwrap()).unwrap();
+        }
+    });
+
+    // Sequential attempts, then racing ones.
+    for attempt_delay in [None, Some(Duration::from_millis(50))] {
+        let options = ConnectOptions {
+            connect_timeout: Some(Duration::from_secs(1)),
+            handshake_timeout: Some(Duration::from_secs(1)),
+            attempt_delay,
+            ..ConnectOptions::default()
+        };
+        let url = format!("ws://localhost:{}/socket", port);
+        connect_with_options(url, None, options).expect("Can't connect");
+    }
+
+    server_thread.join().unwrap();
+}
@@ -14,7 +14,7 @@ use net2::TcpStreamExt;
 use tungstenite::{accept, connect, stream::MaybeTlsStream, Error, Message, WebSocket};
 use url::Url;
 
-type Sock = WebSocket<MaybeTlsStream<TcpStream>>;
+type Sock = WebSocket<'static, MaybeTlsStream<TcpStream>>;
 
 fn do_test<CT, ST>(port: u16, client_task: CT, server_task: ST)
 where
@@ -0,0 +1,88 @@
+//! Verifies that the server gives up on clients that do not complete the handshake in time.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+use tungstenite::{
+    handshake::{server::NoCallback, HandshakeConfig},
+    protocol::WebSocketConfig,
+    Error, HandshakeError, ServerHandshake,
+};
+
+/// Sends the start of a request slowly, then waits for whatever the server answers.
+fn slow_client(port: u16) -> Vec<u8> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    for byte in b"GET /" {
+        stream.write_all(&[*byte]).unwrap();
+        sleep(Duration::from_millis(20));
+    }
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    response
+}
+
+fn serve(send_timeout_response: bool) -> Vec<u8> {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let client_thread = spawn(move || slow_client(port));
+
+    let stream = server.incoming().next().unwrap().unwrap();
+    // The deadline is checked between reads, which time out regularly.
+    stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+    let handshake = HandshakeConfig {
+        timeout: Some(Duration::from_millis(200)),
+        send_timeout_response,
+        ..HandshakeConfig::default()
+    };
+    let config = WebSocketConfig { handshake, ..WebSocketConfig::default() };
+    let mut result = ServerHandshake::start(stream, NoCallback, Some(config)).handshake();
+    loop {
+        match result {
+            Err(HandshakeError::Interrupted(mid)) => result = mid.handshake(),
+            Err(HandshakeError::Failure(Error::HandshakeTimeout)) => break,
+            Err(HandshakeError::Failure(e)) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("handshake completed"),
+        }
+    }
+    client_thread.join().unwrap()
+}
+
+#[test]
+fn test_handshake_timeout() {
+    common::start_watchdog();
+
+    assert_eq!(serve(false), b"");
+    let response = serve(true);
+    assert!(response.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+}
+
+#[test]
+fn test_handshake_timeout_blocking() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let client_thread = spawn(move || slow_client(port));
+
+    // No read timeout on the stream, the handshake applies the deadline itself.
+    let stream = server.incoming().next().unwrap().unwrap();
+    let handshake = HandshakeConfig {
+        timeout: Some(Duration::from_millis(200)),
+        send_timeout_response: true,
+        ..HandshakeConfig::default()
+    };
+    let config = WebSocketConfig { handshake, ..WebSocketConfig::default() };
+    let result =
+        ServerHandshake::start(stream, NoCallback, Some(config)).with_stream_timeouts().handshake();
+    assert!(matches!(result, Err(HandshakeError::Failure(Error::HandshakeTimeout))));
+    let response = client_thread.join().unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+}
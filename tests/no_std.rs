@@ -0,0 +1,28 @@
+//! Verifies that the crate builds without `std` for a bare-metal target.
+
+use std::process::Command;
+
+const TARGET: &str = "thumbv7em-none-eabihf";
+
+/// Builds like `scripts/no_std.sh`: `core` and `alloc` are built from source with a nightly
+/// toolchain, so the target does not need to be installed.
+#[test]
+#[ignore = "needs a nightly toolchain with rust-src, run scripts/no_std.sh or pass --ignored"]
+fn build_without_std() {
+    let builds = [
+        ("core", ""),
+        ("core", "defmt"),
+        ("core,alloc", "alloc"),
+        ("core,alloc", "alloc,defmt"),
+    ];
+    for (crates, features) in builds {
+        let status = Command::new("cargo")
+            .args(["+nightly", "build", "-Z", &format!("build-std={}", crates), "--lib"])
+            .args(["--no-default-features", "--features", features])
+            .args(["--target", TARGET, "--target-dir", env!("CARGO_TARGET_TMPDIR")])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "Building for {} with {} failed", TARGET, features);
+    }
+}
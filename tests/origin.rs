@@ -0,0 +1,70 @@
+//! Verifies that the server rejects requests from disallowed origins and hosts with
+//! `403 Forbidden` before the user callback runs.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{net::TcpListener, thread::spawn};
+
+use tungstenite::{
+    accept_hdr,
+    client::IntoClientRequest,
+    connect,
+    handshake::server::{OriginCallback, OriginPolicy, Request, Response},
+    Error, HandshakeError,
+};
+
+#[test]
+fn test_origin_allow_list() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    let client_thread = spawn(move || {
+        let connect_from = |origin: &str| {
+            let url = format!("ws://localhost:{}/socket", port);
+            let mut request = url.into_client_request().unwrap();
+            request.headers_mut().insert("Origin", origin.parse().unwrap());
+            connect(request)
+        };
+
+        connect_from("https://app.example.com").expect("Can't connect");
+        match connect_from("https://evil.example") {
+            Err(Error::Http(response)) => {
+                assert_eq!(response.status(), 403);
+                assert_eq!(response.body().as_deref(), Some(&b"Origin not allowed"[..]));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    });
+
+    // Accepts the next connection, telling whether the user callback was called.
+    let policy = OriginPolicy::wildcard_subdomain("https://*.example.com").unwrap();
+    let accept_next = || {
+        let mut called = false;
+        let callback = OriginCallback::new(policy.clone(), |_: &Request, response: Response| {
+            called = true;
+            Ok(response)
+        })
+        .allow_hosts(vec!["localhost"]);
+        let result = accept_hdr(server.incoming().next().unwrap().unwrap(), callback);
+        let result = result.map(|_| ()).map_err(|e| match e {
+            HandshakeError::Failure(e) => e,
+            HandshakeError::Interrupted(_) => unreachable!("blocking stream"),
+        });
+        (result, called)
+    };
+
+    let (result, called) = accept_next();
+    result.unwrap();
+    assert!(called);
+
+    match accept_next() {
+        (Err(Error::Http(response)), false) => assert_eq!(response.status(), 403),
+        (other, called) => panic!("unexpected result: {:?}, called: {}", other, called),
+    }
+
+    client_thread.join().unwrap();
+}
@@ -0,0 +1,128 @@
+//! Verifies that `ReconnectingClient` re-dials after a lost connection and stops on
+//! terminal errors.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{
+    io,
+    net::TcpListener,
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+use tungstenite::{
+    accept, accept_hdr,
+    handshake::server::{ErrorResponse, Request, Response},
+    DefaultReconnectPolicy, Error, Message, ReconnectConfig, ReconnectingClient,
+};
+
+#[test]
+fn test_reconnect_after_reset() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        // The first connection is dropped right after the subscription.
+        let mut socket = accept(server.incoming().next().unwrap().unwrap()).unwrap();
+        assert!(socket.read().unwrap().is_text());
+        drop(socket);
+
+        let mut socket = accept(server.incoming().next().unwrap().unwrap()).unwrap();
+        assert!(socket.read().unwrap().is_text());
+        socket.send(Message::Text("From Server".into())).unwrap();
+        sleep(Duration::from_millis(100));
+    });
+
+    let mut subscriptions = 0;
+    let reconnect = ReconnectConfig {
+        initial_backoff: Duration::from_millis(10),
+        max_attempts: Some(5),
+        ..ReconnectConfig::default()
+    };
+    let mut client = ReconnectingClient::connect_with_config(
+        format!("ws://127.0.0.1:{}/socket", port),
+        None,
+        reconnect,
+        DefaultReconnectPolicy,
+        |socket| {
+            subscriptions += 1;
+            socket.send(Message::Text("subscribe".into()))
+        },
+    )
+    .unwrap();
+    assert_eq!(client.generation(), 1);
+
+    assert!(client.read().unwrap().is_text());
+    assert_eq!(client.generation(), 2);
+    drop(client);
+    assert_eq!(subscriptions, 2);
+
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn test_http_client_error_is_terminal() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        let callback = |_: &Request, _: Response| -> Result<Response, ErrorResponse> {
+            let mut response = ErrorResponse::new(None);
+            *response.status_mut() = http::StatusCode::FORBIDDEN;
+            Err(response)
+        };
+        assert!(accept_hdr(server.incoming().next().unwrap().unwrap(), callback).is_err());
+    });
+
+    let url = format!("ws://127.0.0.1:{}/socket", port);
+    match ReconnectingClient::connect(url, |_| Ok(())) {
+        Err(Error::Http(response)) => assert_eq!(response.status(), 403),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn test_failed_on_connect_keeps_generation() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        drop(accept(server.incoming().next().unwrap().unwrap()).unwrap());
+
+        let mut socket = accept(server.incoming().next().unwrap().unwrap()).unwrap();
+        assert!(socket.read().unwrap().is_text());
+    });
+
+    let mut attempts = 0;
+    let reconnect = ReconnectConfig {
+        initial_backoff: Duration::from_millis(10),
+        max_attempts: Some(5),
+        ..ReconnectConfig::default()
+    };
+    let client = ReconnectingClient::connect_with_config(
+        format!("ws://127.0.0.1:{}/socket", port),
+        None,
+        reconnect,
+        DefaultReconnectPolicy,
+        |socket| {
+            attempts += 1;
+            if attempts == 1 {
+                return Err(Error::Io(io::ErrorKind::ConnectionReset.into()));
+            }
+            socket.send(Message::Text("subscribe".into()))
+        },
+    )
+    .unwrap();
+    assert_eq!(client.generation(), 1);
+    drop(client);
+    assert_eq!(attempts, 2);
+
+    server_thread.join().unwrap();
+}
@@ -0,0 +1,117 @@
+//! Verifies how `connect_with_options` follows redirects during the handshake.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread::spawn,
+};
+
+use tungstenite::{
+    accept_hdr,
+    client::{connect_with_options, ConnectOptions},
+    handshake::server::{ErrorResponse, Request, Response},
+    ClientRequestBuilder, Error,
+};
+
+/// Reads a handshake request and answers it with a redirect, returns the lowercased request.
+fn redirect(stream: &mut TcpStream, status: &str, location: &str) -> String {
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        request.push(byte[0]);
+    }
+    let response = format!("HTTP/1.1 {}\r\nLocation: {}\r\n\r\n", status, location);
+    stream.write_all(response.as_bytes()).unwrap();
+    String::from_utf8(request).unwrap().to_ascii_lowercase()
+}
+
+#[test]
+fn test_redirect_strips_credentials_cross_origin() {
+    common::start_watchdog();
+
+    let first = TcpListener::bind("127.0.0.1:0").unwrap();
+    let first_port = first.local_addr().unwrap().port();
+    let second = TcpListener::bind("127.0.0.1:0").unwrap();
+    let second_port = second.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        // Same-origin relative redirect keeps the credentials.
+        let request = redirect(&mut first.accept().unwrap().0, "307 Temporary Redirect", "/moved");
+        assert!(request.starts_with("get /socket http/1.1\r\n"));
+        assert!(request.contains("authorization: bearer secret\r\n"));
+
+        let location = format!("ws://localhost:{}/final", second_port);
+        let request = redirect(&mut first.accept().unwrap().0, "308 Permanent Redirect", &location);
+        assert!(request.starts_with("get /moved http/1.1\r\n"));
+        assert!(request.contains("authorization: bearer secret\r\n"));
+
+        let callback = |request: &Request, response: Response| -> Result<_, ErrorResponse> {
+            assert_eq!(request.uri().path(), "/final");
+            assert_eq!(request.headers()["Host"], format!("localhost:{}", second_port).as_str());
+            assert!(request.headers().get("Authorization").is_none());
+            assert!(request.headers().get("Cookie").is_none());
+            assert_eq!(request.headers()["X-Custom"], "kept");
+            Ok(response)
+        };
+        accept_hdr(second.accept().unwrap().0, callback).unwrap();
+    });
+
+    let url = format!("ws://127.0.0.1:{}/socket", first_port);
+    let request = ClientRequestBuilder::new(url.parse().unwrap())
+        .with_bearer_auth("secret")
+        .with_cookie("session", "42")
+        .with_header("X-Custom", "kept");
+    let hops = Arc::new(Mutex::new(Vec::new()));
+    let seen = hops.clone();
+    let options = ConnectOptions {
+        on_redirect: Some(Arc::new(move |redirect| {
+            let hop = (redirect.hop, redirect.status.as_u16(), redirect.to.clone());
+            seen.lock().unwrap().push(hop);
+            true
+        })),
+        ..ConnectOptions::default()
+    };
+    connect_with_options(request, None, options).expect("Can't connect");
+
+    let hops = hops.lock().unwrap();
+    assert_eq!(hops.len(), 2);
+    assert_eq!(hops[0].0, 1);
+    assert_eq!(hops[0].1, 307);
+    assert_eq!(hops[0].2.to_string(), format!("ws://127.0.0.1:{}/moved", first_port));
+    assert_eq!(hops[1].2.to_string(), format!("ws://localhost:{}/final", second_port));
+
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn test_redirect_vetoed_or_not_followed() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://127.0.0.1:{}/socket", server.local_addr().unwrap().port());
+    let server_thread = spawn(move || {
+        redirect(&mut server.accept().unwrap().0, "302 Found", "/elsewhere");
+        redirect(&mut server.accept().unwrap().0, "300 Multiple Choices", "/elsewhere");
+    });
+
+    let options = ConnectOptions {
+        on_redirect: Some(Arc::new(|_| false)),
+        ..ConnectOptions::default()
+    };
+    match connect_with_options(url.as_str(), None, options) {
+        Err(Error::Http(response)) => assert_eq!(response.status(), 302),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    // Not a redirect to a new location, returned as is.
+    match connect_with_options(url, None, ConnectOptions::default()) {
+        Err(Error::Http(response)) => assert_eq!(response.status(), 300),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    server_thread.join().unwrap();
+}
@@ -0,0 +1,56 @@
+//! Verifies that a `StreamCallback` sees the stream of the handshake and that the data it
+//! attaches is available from the resulting WebSocket.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread::spawn,
+};
+
+use tungstenite::{
+    accept_hdr, connect,
+    handshake::server::{Request, Response, StreamCallbackFn},
+    http::Extensions,
+    stream::MaybeTlsStream,
+    Message,
+};
+
+/// Authentication result of a connection.
+#[derive(Debug, PartialEq)]
+struct User(String);
+
+#[test]
+fn test_stream_callback_extensions() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    let client_thread = spawn(move || {
+        let (mut client, _) =
+            connect(format!("ws://localhost:{}/socket?user=alice", port)).unwrap();
+        client.send(Message::Text("Hello".into())).unwrap();
+        match client.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.local_addr().unwrap(),
+            other => panic!("unexpected stream: {:?}", other),
+        }
+    });
+
+    let callback = StreamCallbackFn::new(
+        |stream: &TcpStream, request: &Request, response: Response, extensions: &mut Extensions| {
+            extensions.insert(stream.peer_addr().unwrap());
+            let user = request.uri().query().and_then(|q| q.strip_prefix("user=")).unwrap();
+            extensions.insert(User(user.into()));
+            Ok(response)
+        },
+    );
+    let mut socket = accept_hdr(server.incoming().next().unwrap().unwrap(), callback).unwrap();
+    assert_eq!(socket.extensions().get::<User>(), Some(&User("alice".into())));
+    assert!(socket.read().unwrap().is_text());
+
+    let peer_addr = *socket.extensions().get::<SocketAddr>().unwrap();
+    assert_eq!(peer_addr, client_thread.join().unwrap());
+}
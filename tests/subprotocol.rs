@@ -0,0 +1,38 @@
+//! Verifies that a subprotocol is negotiated during the handshake and visible on both ends.
+
+#![cfg(feature = "handshake")]
+
+mod common;
+
+use std::{net::TcpListener, thread::spawn};
+
+use tungstenite::{
+    accept_hdr, client::IntoClientRequest, connect, handshake::server::SubprotocolCallback,
+};
+
+#[test]
+fn test_subprotocol_negotiation() {
+    common::start_watchdog();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    let client_thread = spawn(move || {
+        let url = format!("ws://localhost:{}/socket", port);
+        let mut request = url.into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", "chat, superchat".parse().unwrap());
+
+        let (client, response) = connect(request).unwrap();
+        assert_eq!(response.headers().get("Sec-WebSocket-Protocol").unwrap(), "superchat");
+        assert_eq!(client.subprotocol(), Some("superchat"));
+    });
+
+    let client_handler = server.incoming().next().unwrap();
+    let callback = SubprotocolCallback::with_selector(["superchat", "chat"]);
+    let client_handler = accept_hdr(client_handler.unwrap(), callback).unwrap();
+    assert_eq!(client_handler.subprotocol(), Some("superchat"));
+
+    client_thread.join().unwrap();
+}
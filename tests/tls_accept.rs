@@ -0,0 +1,197 @@
+//! Verifies that `accept_tls` performs the TLS and the WebSocket handshake on the server,
+//! on blocking as well as on non-blocking sockets.
+
+#![cfg(all(any(feature = "native-tls", feature = "__rustls-tls"), feature = "handshake"))]
+
+mod common;
+
+use std::{
+    net::{TcpListener, TcpStream},
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+use tungstenite::{
+    accept_tls, client_tls_with_config, Acceptor, Connector, HandshakeError, Message,
+};
+
+/// Connects over TLS and exchanges a message with the server.
+fn run_client(port: u16, connector: Connector) {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    // Let a non-blocking server run into `WouldBlock` before the TLS handshake starts.
+    sleep(Duration::from_millis(50));
+    let url = format!("wss://localhost:{}/socket", port);
+    let (mut client, _) = client_tls_with_config(url, stream, None, Some(connector)).unwrap();
+    client.send(Message::Text("Hello over TLS".into())).unwrap();
+    assert!(client.read().unwrap().is_text());
+}
+
+#[cfg(feature = "__rustls-tls")]
+mod rustls_tests {
+    use std::sync::Arc;
+
+    use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+
+    use super::*;
+
+    fn configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let der = Certificate(cert.serialize_der().unwrap());
+
+        let server = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![der.clone()], PrivateKey(cert.serialize_private_key_der()))
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&der).unwrap();
+        let client = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        (Arc::new(server), Arc::new(client))
+    }
+
+    #[test]
+    fn test_accept_tls_rustls() {
+        common::start_watchdog();
+
+        let (server_config, client_config) = configs();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let client_thread = spawn(move || run_client(port, Connector::Rustls(client_config)));
+
+        let stream = server.incoming().next().unwrap().unwrap();
+        let mut socket = accept_tls(stream, &Acceptor::Rustls(server_config)).unwrap();
+        assert!(socket.read().unwrap().is_text());
+        socket.send(Message::Text("From Server".into())).unwrap();
+
+        client_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_accept_tls_rustls_non_blocking() {
+        common::start_watchdog();
+
+        let (server_config, client_config) = configs();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let client_thread = spawn(move || run_client(port, Connector::Rustls(client_config)));
+
+        let stream = server.incoming().next().unwrap().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let raw = stream.try_clone().unwrap();
+        let mut interrupted = 0;
+        let mut result = accept_tls(stream, &Acceptor::Rustls(server_config));
+        let mut socket = loop {
+            match result {
+                Ok(socket) => break socket,
+                Err(HandshakeError::Interrupted(mid)) => {
+                    interrupted += 1;
+                    sleep(Duration::from_millis(1));
+                    result = mid.handshake();
+                }
+                Err(HandshakeError::Failure(e)) => panic!("handshake failed: {}", e),
+            }
+        };
+        assert!(interrupted > 0);
+
+        raw.set_nonblocking(false).unwrap();
+        assert!(socket.read().unwrap().is_text());
+        socket.send(Message::Text("From Server".into())).unwrap();
+
+        client_thread.join().unwrap();
+    }
+}
+
+#[cfg(feature = "native-tls")]
+mod native_tls_tests {
+    use native_tls_crate::{Certificate, Identity, TlsAcceptor, TlsConnector};
+    use tungstenite::{error::TlsError, Error};
+
+    use super::*;
+
+    #[test]
+    fn test_accept_tls_native_non_blocking() {
+        common::start_watchdog();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+        let identity =
+            Identity::from_pkcs8(pem.as_bytes(), cert.serialize_private_key_pem().as_bytes());
+        let acceptor = TlsAcceptor::new(identity.unwrap()).unwrap();
+        let connector = TlsConnector::builder()
+            .add_root_certificate(Certificate::from_pem(pem.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let client_thread = spawn(move || run_client(port, Connector::NativeTls(connector)));
+
+        let stream = server.incoming().next().unwrap().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let raw = stream.try_clone().unwrap();
+        let mut interrupted = 0;
+        let mut result = accept_tls(stream, &Acceptor::NativeTls(acceptor));
+        let mut socket = loop {
+            match result {
+                Ok(socket) => break socket,
+                Err(HandshakeError::Interrupted(mid)) => {
+                    interrupted += 1;
+                    sleep(Duration::from_millis(1));
+                    result = mid.handshake();
+                }
+                Err(HandshakeError::Failure(e)) => panic!("handshake failed: {}", e),
+            }
+        };
+        assert!(interrupted > 0);
+
+        raw.set_nonblocking(false).unwrap();
+        assert!(socket.read().unwrap().is_text());
+        socket.send(Message::Text("From Server".into())).unwrap();
+
+        client_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_accept_tls_native_failure() {
+        common::start_watchdog();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let identity = Identity::from_pkcs8(
+            cert.serialize_pem().unwrap().as_bytes(),
+            cert.serialize_private_key_pem().as_bytes(),
+        );
+        let acceptor = TlsAcceptor::new(identity.unwrap()).unwrap();
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        // The client does not trust the self-signed certificate and aborts the handshake.
+        let client_thread = spawn(move || {
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            sleep(Duration::from_millis(50));
+            let url = format!("wss://localhost:{}/socket", port);
+            let connector = Connector::NativeTls(TlsConnector::new().unwrap());
+            assert!(client_tls_with_config(url, stream, None, Some(connector)).is_err());
+        });
+
+        let stream = server.incoming().next().unwrap().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut result = accept_tls(stream, &Acceptor::NativeTls(acceptor));
+        loop {
+            match result {
+                Err(HandshakeError::Interrupted(mid)) => {
+                    sleep(Duration::from_millis(1));
+                    result = mid.handshake();
+                }
+                Err(HandshakeError::Failure(Error::Tls(TlsError::Native(_)))) => break,
+                Err(HandshakeError::Failure(e)) => panic!("unexpected error: {}", e),
+                Ok(_) => panic!("handshake completed"),
+            }
+        }
+
+        client_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! Verifies that the `TlsOptions` of `connect_with_options` present a client certificate, trust
+//! extra roots, override the server name, offer ALPN protocols and pin server certificates, and
+//! that a `Connector` built from them pins server certificates as well.
+
+#![cfg(all(feature = "__rustls-tls", feature = "handshake"))]
+
+mod common;
+
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread::{spawn, JoinHandle},
+};
+
+use rustls::{server::AllowAnyAuthenticatedClient, PrivateKey, RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use tungstenite::{
+    accept_tls,
+    client::{connect_with_options, ConnectOptions, TlsOptions},
+    client_tls_with_config,
+    error::TlsError,
+    stream::MaybeTlsStream,
+    Acceptor, Connector, Error, HandshakeError, Message,
+};
+
+/// A self-signed certificate, serialized exactly once as every serialization signs anew.
+struct SelfSigned {
+    pem: Vec<u8>,
+    der: rustls::Certificate,
+    key_pem: Vec<u8>,
+    spki_sha256: [u8; 32],
+}
+
+fn self_signed(name: &str) -> SelfSigned {
+    let cert = rcgen::generate_simple_self_signed(vec![name.into()]).unwrap();
+    let pem = cert.serialize_pem().unwrap().into_bytes();
+    let der = rustls::Certificate(rustls_pemfile::certs(&mut &pem[..]).unwrap().remove(0));
+    SelfSigned {
+        pem,
+        der,
+        key_pem: cert.serialize_private_key_pem().into_bytes(),
+        spki_sha256: Sha256::digest(cert.get_key_pair().public_key_der()).into(),
+    }
+}
+
+fn pkcs8_der(pem: &[u8]) -> Vec<u8> {
+    rustls_pemfile::pkcs8_private_keys(&mut &pem[..]).unwrap().remove(0)
+}
+
+#[test]
+fn test_mutual_tls() {
+    common::start_watchdog();
+
+    let server_cert = self_signed("ws.internal");
+    let client_cert = self_signed("client");
+
+    let mut client_roots = RootCertStore::empty();
+    client_roots.add(&client_cert.der).unwrap();
+    let mut server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots).boxed())
+        .with_single_cert(vec![server_cert.der], PrivateKey(pkcs8_der(&server_cert.key_pem)))
+        .unwrap();
+    server_config.alpn_protocols = vec![b"ws-test".to_vec()];
+    let acceptor = Acceptor::Rustls(Arc::new(server_config));
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        let stream = server.incoming().next().unwrap().unwrap();
+        let mut socket = accept_tls(stream, &acceptor).unwrap();
+        match socket.get_ref() {
+            MaybeTlsStream::RustlsServer(tls) => {
+                assert_eq!(tls.conn.alpn_protocol(), Some(&b"ws-test"[..]));
+                assert_eq!(tls.conn.peer_certificates().map(|certs| certs.len()), Some(1));
+            }
+            other => panic!("unexpected stream: {:?}", other),
+        }
+        assert!(socket.read().unwrap().is_text());
+    });
+
+    let tls = TlsOptions {
+        client_cert_pem: Some(client_cert.pem),
+        client_key_pem: Some(client_cert.key_pem),
+        root_certs_pem: vec![server_cert.pem],
+        server_name: Some("ws.internal".into()),
+        alpn_protocols: vec![b"http/1.1".to_vec(), b"ws-test".to_vec()],
+        ..TlsOptions::default()
+    };
+    let options = ConnectOptions { tls: Some(tls), ..ConnectOptions::default() };
+    let (mut client, _) =
+        connect_with_options(format!("wss://127.0.0.1:{}/socket", port), None, options)
+            .expect("Can't connect");
+    client.send(Message::Text("Hello over mTLS".into())).unwrap();
+
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn test_client_cert_without_key() {
+    let tls = TlsOptions { client_cert_pem: Some(Vec::new()), ..TlsOptions::default() };
+    let options = ConnectOptions { tls: Some(tls), ..ConnectOptions::default() };
+
+    // The options are rejected after the TCP connection, before the TLS handshake starts.
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || drop(server.incoming().next()));
+    match connect_with_options(format!("wss://127.0.0.1:{}/socket", port), None, options) {
+        Err(Error::Tls(TlsError::InvalidPem(_))) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    server_thread.join().unwrap();
+}
+
+/// Serves a single connection with an untrusted certificate, expecting one message.
+///
+/// Returns the port of the server.
+fn serve_untrusted(cert: &SelfSigned) -> (u16, JoinHandle<bool>) {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.der.clone()], PrivateKey(pkcs8_der(&cert.key_pem)))
+        .unwrap();
+    let acceptor = Acceptor::Rustls(Arc::new(config));
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server_thread = spawn(move || {
+        let stream = server.incoming().next().unwrap().unwrap();
+        match accept_tls(stream, &acceptor) {
+            Ok(mut socket) => socket.read().unwrap().is_text(),
+            Err(_) => false,
+        }
+    });
+    (port, server_thread)
+}
+
+fn connect_pinned(port: u16, tls: TlsOptions) -> Result<(), Error> {
+    let url = format!("wss://localhost:{}/socket", port);
+    let options = ConnectOptions { tls: Some(tls), ..ConnectOptions::default() };
+    let (mut client, _) = connect_with_options(url, None, options)?;
+    client.send(Message::Text("Hello to a pinned server".into()))
+}
+
+#[test]
+fn test_spki_pin() {
+    common::start_watchdog();
+
+    let cert = self_signed("localhost");
+    let (port, server_thread) = serve_untrusted(&cert);
+    let tls = TlsOptions { spki_sha256_pins: vec![cert.spki_sha256], ..TlsOptions::default() };
+    connect_pinned(port, tls).unwrap();
+    assert!(server_thread.join().unwrap());
+}
+
+#[test]
+fn test_spki_pin_mismatch() {
+    common::start_watchdog();
+
+    let cert = self_signed("localhost");
+    let (port, server_thread) = serve_untrusted(&cert);
+    let tls = TlsOptions { spki_sha256_pins: vec![[0; 32]], ..TlsOptions::default() };
+    match connect_pinned(port, tls) {
+        Err(Error::Tls(TlsError::PinMismatch)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(!server_thread.join().unwrap());
+}
+
+#[test]
+fn test_verifier_callback() {
+    common::start_watchdog();
+
+    let cert = self_signed("localhost");
+    let (port, server_thread) = serve_untrusted(&cert);
+    let expected = cert.spki_sha256;
+    let tls = TlsOptions {
+        verifier: Some(Arc::new(move |cert| {
+            !cert.trusted && cert.intermediates.is_empty() && cert.spki_sha256 == expected
+        })),
+        ..TlsOptions::default()
+    };
+    connect_pinned(port, tls).unwrap();
+    assert!(server_thread.join().unwrap());
+
+    let (port, server_thread) = serve_untrusted(&cert);
+    let tls = TlsOptions { verifier: Some(Arc::new(|_| false)), ..TlsOptions::default() };
+    match connect_pinned(port, tls) {
+        Err(Error::Tls(TlsError::CertificateRejected)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(!server_thread.join().unwrap());
+}
+
+#[test]
+fn test_spki_pin_connector() {
+    common::start_watchdog();
+
+    let cert = self_signed("localhost");
+    for (pin, accepted) in [(cert.spki_sha256, true), ([0; 32], false)] {
+        let (port, server_thread) = serve_untrusted(&cert);
+        let tls = TlsOptions { spki_sha256_pins: vec![pin], ..TlsOptions::default() };
+        let connector = Connector::from_options(&tls).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let url = format!("wss://localhost:{}/socket", port);
+        match client_tls_with_config(url, stream, None, Some(connector)) {
+            Ok((mut client, _)) => {
+                assert!(accepted);
+                client.send(Message::Text("Hello to a pinned server".into())).unwrap();
+            }
+            Err(HandshakeError::Failure(Error::Tls(TlsError::PinMismatch))) => assert!(!accepted),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(server_thread.join().unwrap(), accepted);
+    }
+}
@@ -0,0 +1,108 @@
+//! Verifies that clients and servers can talk WebSocket over Unix domain sockets.
+
+#![cfg(all(unix, feature = "handshake"))]
+
+mod common;
+
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::net::UnixListener,
+    path::PathBuf,
+    process,
+    thread::spawn,
+};
+
+use tungstenite::{
+    accept_unix, accept_unix_hdr_with_config, connect, connect_unix, connect_unix_socket,
+    handshake::server::{Request, Response},
+    stream::MaybeTlsStream,
+    ClientRequestBuilder, Error, Message,
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tungstenite-{}-{}.sock", process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_unix_socket_url() {
+    common::start_watchdog();
+
+    let path = socket_path("url");
+    let listener = UnixListener::bind(&path).unwrap();
+    let server_thread = spawn(move || {
+        let callback = |request: &Request, response: Response| {
+            assert_eq!(request.uri(), "/events?since=1");
+            assert_eq!(request.headers()["host"], "localhost");
+            Ok(response)
+        };
+        let mut socket = accept_unix_hdr_with_config(&listener, callback, None).unwrap();
+        assert!(socket.read().unwrap().is_text());
+        socket.send(Message::Text("From Server".into())).unwrap();
+    });
+
+    let url = format!("ws+unix://{}:/events?since=1", path.display());
+    let (mut client, _) = connect_unix(&url).expect("Can't connect");
+    client.send(Message::Text("Hello over a Unix socket".into())).unwrap();
+    assert!(client.read().unwrap().is_text());
+
+    server_thread.join().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_unix_socket_connect() {
+    common::start_watchdog();
+
+    // The socket path contains a colon, only the one before `/` starts the request path.
+    let path = socket_path("con:nect");
+    let listener = UnixListener::bind(&path).unwrap();
+    let server_thread = spawn(move || {
+        let callback = |request: &Request, response: Response| {
+            assert_eq!(request.uri(), "/events");
+            Ok(response)
+        };
+        let mut socket = accept_unix_hdr_with_config(&listener, callback, None).unwrap();
+        assert!(socket.read().unwrap().is_text());
+    });
+
+    let url = format!("ws+unix://{}:/events", path.display());
+    let (mut client, _) = connect(url).expect("Can't connect");
+    assert!(matches!(client.get_ref(), MaybeTlsStream::Unix(_)));
+    client.send(Message::Text("Hello through connect".into())).unwrap();
+
+    server_thread.join().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_unix_socket_custom_request() {
+    common::start_watchdog();
+
+    let path = socket_path("request");
+    let listener = UnixListener::bind(&path).unwrap();
+    let server_thread = spawn(move || {
+        let mut socket = accept_unix(&listener).unwrap();
+        assert!(socket.read().unwrap().is_text());
+    });
+
+    let request = ClientRequestBuilder::new("ws://sidecar/api".parse().unwrap())
+        .with_header("Authorization", "Bearer token");
+    let (mut client, _) = connect_unix_socket(&path, request, None).expect("Can't connect");
+    client.send(Message::Text("Hello sidecar".into())).unwrap();
+
+    server_thread.join().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_unix_socket_missing() {
+    let path = socket_path("missing");
+    let url = format!("ws+unix://{}", path.display());
+    match connect_unix(&url) {
+        Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}